@@ -0,0 +1,67 @@
+#import bevy_pbr::mesh_view_bind_group
+
+struct Settings {
+    // Layer opacity, tile size
+    values: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] world_position: vec4<f32>;
+    [[location(1)]] world_normal: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+};
+
+[[group(1), binding(0)]]
+var atlas: texture_2d<f32>;
+// Glyph index, flip | rotation << 1, fg, bg
+[[group(1), binding(1)]]
+var cells: texture_2d<u32>;
+[[group(1), binding(2)]]
+var palette: texture_2d<f32>;
+[[group(1), binding(3)]]
+var<uniform> settings: Settings;
+
+fn palette_color(index: u32) -> vec4<f32> {
+    // Transparent, then indices past the end of the palette in magenta like `colors::MISSING`
+    if (index == 65535u) {
+        return vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    }
+    if (index >= u32(textureDimensions(palette).x)) {
+        return vec4<f32>(1.0, 0.0, 1.0, 1.0);
+    }
+    return textureLoad(palette, vec2<i32>(i32(index), 0), 0);
+}
+
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let size = textureDimensions(cells);
+    let pos = in.uv * vec2<f32>(size);
+    // The uv are y down, the rows of the grid y up
+    let cell_pos = min(vec2<i32>(floor(pos)), size - vec2<i32>(1, 1));
+    let cell = textureLoad(cells, vec2<i32>(cell_pos.x, size.y - 1 - cell_pos.y), 0);
+
+    // Position in the glyph, undoing the rotations then the flip
+    var p = fract(pos);
+    let rotation = cell.y >> 1u;
+    for (var i = 0u; i < rotation; i = i + 1u) {
+        p = vec2<f32>(1.0 - p.y, p.x);
+    }
+    if ((cell.y & 1u) == 1u) {
+        p.x = 1.0 - p.x;
+    }
+
+    let tile_size = i32(settings.values.y);
+    let columns = textureDimensions(atlas).x / tile_size;
+    let glyph = i32(cell.x);
+    let pixel = min(vec2<i32>(p * f32(tile_size)), vec2<i32>(tile_size - 1, tile_size - 1));
+    let texel = textureLoad(atlas, vec2<i32>(glyph % columns, glyph / columns) * tile_size + pixel, 0);
+
+    var color: vec4<f32>;
+    if (texel[0] == 0.0) {
+        color = palette_color(cell.w);
+    } else {
+        color = palette_color(cell.z);
+    }
+    return vec4<f32>(color.rgb, color.a * settings.values.x);
+}
@@ -4,6 +4,11 @@ struct ColorWrapper {
     color: vec4<f32>;
 };
 
+struct Glyph {
+    // Index, flip, rotation, tile size
+    values: vec4<f32>;
+};
+
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] world_position: vec4<f32>;
@@ -12,9 +17,9 @@ struct VertexOutput {
 };
 
 [[group(1), binding(0)]]
-var texture: texture_2d<f32>;
+var atlas: texture_2d<f32>;
 [[group(1), binding(1)]]
-var texture_sampler: sampler;
+var<uniform> glyph: Glyph;
 [[group(1), binding(2)]]
 var<uniform> bg: ColorWrapper;
 [[group(1), binding(3)]]
@@ -22,7 +27,21 @@ var<uniform> fg: ColorWrapper;
 
 [[stage(fragment)]]
 fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    let color = textureSample(texture, texture_sampler, in.uv);
+    // Position in the glyph, undoing the rotations then the flip
+    var p = in.uv;
+    let rotation = i32(glyph.values.z);
+    for (var i = 0; i < rotation; i = i + 1) {
+        p = vec2<f32>(1.0 - p.y, p.x);
+    }
+    if (glyph.values.y == 1.0) {
+        p.x = 1.0 - p.x;
+    }
+
+    let tile_size = i32(glyph.values.w);
+    let columns = textureDimensions(atlas).x / tile_size;
+    let index = i32(glyph.values.x);
+    let pixel = clamp(vec2<i32>(p * f32(tile_size)), vec2<i32>(0, 0), vec2<i32>(tile_size - 1, tile_size - 1));
+    let color = textureLoad(atlas, vec2<i32>(index % columns, index / columns) * tile_size + pixel, 0);
     if (color[0] == 0.0) {
         return bg.color;
     } else {
@@ -0,0 +1,358 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use bevy_textmode::{Canvas, Cell, TileColors, TileId, TilePos};
+use crate::Grid;
+use crate::cursor::HoveredTile;
+use crate::eyedropper::pick;
+use crate::gui::{Tool, UiState};
+use crate::history::History;
+use crate::paint::{apply_paint, PaintEvent};
+
+/// Connection bits of a box-drawing glyph. North is towards the top of the screen.
+pub const NORTH: u8 = 1;
+pub const EAST: u8 = 2;
+pub const SOUTH: u8 = 4;
+pub const WEST: u8 = 8;
+
+/// How each connection mask is drawn, in box-drawing characters.
+pub const MASK_LABELS: [&str; 16] = [
+    "·", "╵", "╶", "└", "╷", "│", "┌", "├",
+    "╴", "┘", "─", "┴", "┐", "┤", "┬", "┼",
+];
+
+pub struct BoxDrawingPlugin;
+
+impl Plugin for BoxDrawingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(BoxGlyphs {
+                path: String::new(),
+                single: cp437(&CP437_SINGLE),
+                double: cp437(&CP437_DOUBLE),
+                status: None,
+            })
+            .add_event::<SaveBoxGlyphs>()
+            .add_system(load_box_glyphs)
+            .add_system(save_box_glyphs)
+            .add_system(draw_box.after(pick).before(apply_paint));
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum BoxStyle {
+    Single,
+    Double,
+}
+
+/// CP437 indices of the single line glyphs, by connection mask.
+const CP437_SINGLE: [usize; 16] = [196, 179, 196, 192, 179, 179, 218, 195, 196, 217, 196, 193, 191, 180, 194, 197];
+/// CP437 indices of the double line glyphs, by connection mask.
+const CP437_DOUBLE: [usize; 16] = [205, 186, 205, 200, 186, 186, 201, 204, 205, 188, 205, 202, 187, 185, 203, 206];
+
+fn cp437(indices: &[usize; 16]) -> [TileId; 16] {
+    indices.map(|index| TileId { index, flip: false, rotation: 0 })
+}
+
+/// Glyph used for each connection mask, for both line styles.
+///
+/// Read from `<tileset>.box.ron`, CP437 indices are used when it doesn't exist.
+pub struct BoxGlyphs {
+    pub path: String,
+    pub single: [TileId; 16],
+    pub double: [TileId; 16],
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BoxGlyphsFile {
+    single: [TileId; 16],
+    double: [TileId; 16],
+}
+
+impl BoxGlyphs {
+    pub fn table(&self, style: BoxStyle) -> &[TileId; 16] {
+        match style {
+            BoxStyle::Single => &self.single,
+            BoxStyle::Double => &self.double,
+        }
+    }
+
+    /// Connections of `id` if it's a glyph of `style`.
+    ///
+    /// When a glyph is used for several masks (e.g. line ends), the most connected one wins.
+    pub fn mask(&self, style: BoxStyle, id: &TileId) -> Option<u8> {
+        self.table(style).iter().enumerate()
+            .filter(|(_, glyph)| *glyph == id)
+            .map(|(mask, _)| mask as u8)
+            .max_by_key(|mask| mask.count_ones())
+    }
+}
+
+/// Writes the current [`BoxGlyphs`] next to the tileset.
+pub struct SaveBoxGlyphs;
+
+fn box_glyphs_path(tileset: &str) -> String {
+    Path::new(tileset).with_extension("box.ron").to_string_lossy().to_string()
+}
+
+fn load_box_glyphs(
+    canvas: Res<Canvas>,
+    mut glyphs: ResMut<BoxGlyphs>,
+) {
+    let path = box_glyphs_path(&canvas.tileset);
+    if glyphs.path == path { return; }
+
+    let file = fs::read_to_string(&path).ok().map(|text| ron::from_str::<BoxGlyphsFile>(&text));
+    let (single, double, status) = match file {
+        Some(Ok(file)) => (file.single, file.double, None),
+        Some(Err(e)) => (cp437(&CP437_SINGLE), cp437(&CP437_DOUBLE), Some(format!("Invalid {}: {}", path, e))),
+        None => (cp437(&CP437_SINGLE), cp437(&CP437_DOUBLE), None),
+    };
+    *glyphs = BoxGlyphs { path, single, double, status };
+}
+
+fn save_box_glyphs(
+    mut events: EventReader<SaveBoxGlyphs>,
+    mut glyphs: ResMut<BoxGlyphs>,
+) {
+    for _ in events.iter() {
+        let file = BoxGlyphsFile { single: glyphs.single, double: glyphs.double };
+        let result = ron::ser::to_string_pretty(&file, PrettyConfig::new().depth_limit(2))
+            .map_err(|e| e.to_string())
+            .and_then(|text| fs::write(&glyphs.path, text).map_err(|e| e.to_string()));
+        glyphs.status = Some(match result {
+            Ok(_) => format!("Saved {}", glyphs.path),
+            Err(e) => format!("Couldn't write {}: {}", glyphs.path, e),
+        });
+    }
+}
+
+/// Cells drawn by the current stroke and their connections.
+#[derive(Default)]
+struct BoxStroke {
+    start: Option<TilePos>,
+    last: Option<TilePos>,
+    masks: HashMap<(u32, u32), u8>,
+    /// Cells of the path itself, as opposed to joined neighbors.
+    drawn: HashSet<(u32, u32)>,
+    /// Cells whose glyph must be updated.
+    changed: Vec<TilePos>,
+}
+
+impl BoxStroke {
+    fn draw(&mut self, pos: TilePos, existing: &dyn Fn(&TilePos) -> Option<u8>) {
+        self.masks.entry((pos.x, pos.y)).or_insert_with(|| existing(&pos).unwrap_or(0));
+        self.drawn.insert((pos.x, pos.y));
+        self.changed.push(pos);
+    }
+
+    fn connect(&mut self, a: TilePos, b: TilePos, existing: &dyn Fn(&TilePos) -> Option<u8>) {
+        let direction = direction(a, b);
+        for (pos, bit) in [(a, direction), (b, opposite(direction))] {
+            *self.masks.entry((pos.x, pos.y)).or_insert_with(|| existing(&pos).unwrap_or(0)) |= bit;
+            self.changed.push(pos);
+        }
+    }
+
+    /// Connects `pos` to the adjacent box glyphs which aren't part of the stroke.
+    fn join_neighbors(&mut self, pos: TilePos, existing: &dyn Fn(&TilePos) -> Option<u8>) {
+        for neighbor in neighbors(pos) {
+            if !self.masks.contains_key(&(neighbor.x, neighbor.y)) && existing(&neighbor).is_some() {
+                self.connect(pos, neighbor, existing);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_box(
+    mut egui_ctx: ResMut<EguiContext>,
+    mouse: Res<Input<MouseButton>>,
+    hovered: Res<HoveredTile>,
+    ui_state: Res<UiState>,
+    glyphs: Res<BoxGlyphs>,
+    grid: Res<Grid>,
+    cells: Query<(&TileId, &TileColors)>,
+    mut history: ResMut<History>,
+    mut events: EventWriter<PaintEvent>,
+    mut stroke: Local<BoxStroke>,
+) {
+    if ui_state.tool != Tool::Box { return; }
+    let style = ui_state.box_style;
+    let read = |pos: &TilePos| grid.get(pos)
+        .and_then(|e| cells.get(e).ok())
+        .map(|(id, colors)| Cell { id: *id, fg: colors.fg, bg: colors.bg });
+    let existing = |pos: &TilePos| read(pos).and_then(|cell| glyphs.mask(style, &cell.id));
+
+    if mouse.just_pressed(MouseButton::Left) && !egui_ctx.ctx_mut().is_pointer_over_area() {
+        *stroke = BoxStroke::default();
+        stroke.start = hovered.pos;
+        history.name("Box");
+    }
+    let start = match stroke.start {
+        Some(start) => start,
+        None => return,
+    };
+    let released = !mouse.pressed(MouseButton::Left);
+
+    if ui_state.box_rectangle {
+        if !released {
+            stroke.last = hovered.pos.or(stroke.last);
+        } else if let Some(end) = hovered.pos.or(stroke.last) {
+            let (x0, x1) = (start.x.min(end.x), start.x.max(end.x));
+            let (y0, y1) = (start.y.min(end.y), start.y.max(end.y));
+            for y in y0..=y1 {
+                for x in x0..=x1 {
+                    if x == x0 || x == x1 || y == y0 || y == y1 { stroke.draw(TilePos { x, y }, &existing); }
+                }
+            }
+            for x in x0..x1 {
+                for y in [y0, y1] { stroke.connect(TilePos { x, y }, TilePos { x: x + 1, y }, &existing); }
+            }
+            for y in y0..y1 {
+                for x in [x0, x1] { stroke.connect(TilePos { x, y }, TilePos { x, y: y + 1 }, &existing); }
+            }
+        }
+    } else {
+        if stroke.last.is_none() {
+            stroke.draw(start, &existing);
+            stroke.join_neighbors(start, &existing);
+            stroke.last = Some(start);
+        }
+        if let (Some(last), Some(pos)) = (stroke.last, hovered.pos) {
+            let mut previous = last;
+            for next in orthogonal_path(last, pos) {
+                stroke.draw(next, &existing);
+                stroke.connect(previous, next, &existing);
+                previous = next;
+            }
+            stroke.last = Some(pos);
+        }
+        if released && stroke.last != Some(start) {
+            if let Some(last) = stroke.last { stroke.join_neighbors(last, &existing); }
+        }
+    }
+
+    let mut changed = std::mem::take(&mut stroke.changed);
+    changed.sort_by_key(|p| (p.y, p.x));
+    changed.dedup();
+    for pos in changed {
+        let old = match read(&pos) {
+            Some(cell) => cell,
+            None => continue,
+        };
+        let id = glyphs.table(style)[stroke.masks[&(pos.x, pos.y)] as usize];
+        // Joined neighbors keep their colors
+        let cell = if stroke.drawn.contains(&(pos.x, pos.y)) {
+            Cell { id, fg: ui_state.fg, bg: ui_state.bg }
+        } else {
+            Cell { id, ..old }
+        };
+        events.send(PaintEvent { pos, cell });
+    }
+
+    if released { *stroke = BoxStroke::default(); }
+}
+
+fn direction(from: TilePos, to: TilePos) -> u8 {
+    if to.y > from.y { NORTH } else if to.y < from.y { SOUTH } else if to.x > from.x { EAST } else { WEST }
+}
+
+fn opposite(direction: u8) -> u8 {
+    match direction {
+        NORTH => SOUTH,
+        SOUTH => NORTH,
+        EAST => WEST,
+        _ => EAST,
+    }
+}
+
+fn neighbors(pos: TilePos) -> Vec<TilePos> {
+    let mut result = vec![TilePos { x: pos.x + 1, y: pos.y }, TilePos { x: pos.x, y: pos.y + 1 }];
+    if pos.x > 0 { result.push(TilePos { x: pos.x - 1, y: pos.y }); }
+    if pos.y > 0 { result.push(TilePos { x: pos.x, y: pos.y - 1 }); }
+    result
+}
+
+/// Cells from `a` (excluded) to `b`, moving horizontally first then vertically.
+fn orthogonal_path(a: TilePos, b: TilePos) -> Vec<TilePos> {
+    let mut path = vec![];
+    let mut pos = a;
+    while pos.x != b.x {
+        pos.x = if b.x > pos.x { pos.x + 1 } else { pos.x - 1 };
+        path.push(pos);
+    }
+    while pos.y != b.y {
+        pos.y = if b.y > pos.y { pos.y + 1 } else { pos.y - 1 };
+        path.push(pos);
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cp437_glyphs() -> BoxGlyphs {
+        BoxGlyphs { path: String::new(), single: cp437(&CP437_SINGLE), double: cp437(&CP437_DOUBLE), status: None }
+    }
+
+    #[test]
+    fn masks_of_cp437_glyphs() {
+        let glyphs = cp437_glyphs();
+        for style in [BoxStyle::Single, BoxStyle::Double] {
+            // Line ends share the glyph of the full line
+            for mask in (0..16u8).filter(|mask| mask.count_ones() >= 2) {
+                assert_eq!(glyphs.mask(style, &glyphs.table(style)[mask as usize]), Some(mask));
+            }
+            assert_eq!(glyphs.mask(style, &glyphs.table(style)[EAST as usize]), Some(EAST | WEST));
+            assert_eq!(glyphs.mask(style, &glyphs.table(style)[0]), Some(EAST | WEST));
+        }
+        assert_eq!(glyphs.mask(BoxStyle::Single, &TileId { index: 197, ..TileId::new() }), Some(15));
+        assert_eq!(glyphs.mask(BoxStyle::Single, &TileId { index: 206, ..TileId::new() }), None);
+        assert_eq!(glyphs.mask(BoxStyle::Single, &TileId { index: 197, flip: true, rotation: 0 }), None);
+    }
+
+    #[test]
+    fn stroke_connects_its_cells() {
+        let pos = |x, y| TilePos { x, y };
+        let none = |_: &TilePos| None;
+        let mut stroke = BoxStroke::default();
+        let mut last = pos(0, 0);
+        stroke.draw(last, &none);
+        for next in orthogonal_path(last, pos(2, 2)) {
+            stroke.draw(next, &none);
+            stroke.connect(last, next, &none);
+            last = next;
+        }
+        let mask = |x, y| stroke.masks[&(x, y)];
+        assert_eq!(mask(0, 0), EAST);
+        assert_eq!(mask(1, 0), EAST | WEST);
+        assert_eq!(mask(2, 0), WEST | NORTH);
+        assert_eq!(mask(2, 1), NORTH | SOUTH);
+        assert_eq!(mask(2, 2), SOUTH);
+    }
+
+    #[test]
+    fn joined_neighbors_keep_their_connections() {
+        let pos = |x, y| TilePos { x, y };
+        let existing = |p: &TilePos| if (p.x, p.y) == (1, 0) { Some(NORTH | SOUTH) } else { None };
+        let mut stroke = BoxStroke::default();
+        stroke.draw(pos(1, 1), &existing);
+        stroke.join_neighbors(pos(1, 1), &existing);
+        assert_eq!(stroke.masks[&(1, 1)], SOUTH);
+        assert_eq!(stroke.masks[&(1, 0)], NORTH | SOUTH);
+    }
+
+    #[test]
+    fn paths_go_horizontally_first() {
+        let path = orthogonal_path(TilePos { x: 3, y: 1 }, TilePos { x: 1, y: 2 });
+        assert_eq!(path.iter().map(|p| (p.x, p.y)).collect::<Vec<(u32, u32)>>(), vec![(2, 1), (1, 1), (1, 2)]);
+        for direction in [NORTH, EAST, SOUTH, WEST] { assert_eq!(opposite(opposite(direction)), direction); }
+    }
+}
\ No newline at end of file
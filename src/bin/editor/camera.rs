@@ -0,0 +1,214 @@
+use bevy::input::{ElementState, InputSystem};
+use bevy::input::mouse::{MouseButtonInput, MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_textmode::Canvas;
+use crate::gui::ui;
+use crate::MainCamera;
+
+/// Zoom levels, in screen pixels per tileset pixel when positive and in tileset pixels per
+/// screen pixel when negative, so large canvases still fit in the window. 0 and -1 aren't used.
+pub const MIN_ZOOM: i32 = -8;
+pub const MAX_ZOOM: i32 = 16;
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(View { zoom: 4 })
+            .add_event::<CameraEvent>()
+            .add_system(camera_shortcuts)
+            // Panning runs before the tools so a space+drag doesn't paint
+            .add_system_to_stage(CoreStage::PreUpdate, pan.after(InputSystem))
+            .add_system(zoom)
+            // Fit reads the area left by the side panel, which is only known once the panel is laid out
+            .add_system(handle_camera_events.after(camera_shortcuts).after(ui));
+    }
+}
+
+/// Current zoom of the main camera, see [`MIN_ZOOM`].
+pub struct View {
+    pub zoom: i32,
+}
+
+impl View {
+    /// World units per screen pixel.
+    pub fn scale(&self) -> f32 {
+        if self.zoom > 0 { 1. / self.zoom as f32 } else { -self.zoom as f32 }
+    }
+}
+
+/// Zoom level `steps` levels in from `zoom`, out when negative.
+fn step(zoom: i32, steps: i32) -> i32 {
+    // Number the levels without the gap between -2 and 1
+    let level = if zoom > 0 { zoom - 1 } else { zoom + 1 } + steps;
+    if level >= 0 { level + 1 } else { level - 1 }
+}
+
+/// Largest zoom at which `ratio` times the canvas size fits in the available area.
+fn fit_zoom(ratio: f32) -> i32 {
+    if ratio >= 1. { ratio.floor() as i32 } else { -((1. / ratio).ceil() as i32) }
+}
+
+pub enum CameraEvent {
+    /// Largest zoom showing the whole canvas next to the side panel.
+    Fit,
+    /// One screen pixel per tileset pixel.
+    ActualSize,
+}
+
+fn camera_shortcuts(
+    mut egui_ctx: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    mut events: EventWriter<CameraEvent>,
+) {
+    if egui_ctx.ctx_mut().wants_keyboard_input() { return; }
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if ctrl && keys.any_just_pressed([KeyCode::Key0, KeyCode::Numpad0]) {
+        events.send(CameraEvent::Fit);
+    } else if ctrl && keys.any_just_pressed([KeyCode::Key1, KeyCode::Numpad1]) {
+        events.send(CameraEvent::ActualSize);
+    }
+}
+
+/// Cursor position relative to the center of the window, y up.
+fn cursor_offset(windows: &Windows) -> Option<Vec2> {
+    let window = windows.get_primary()?;
+    let pos = window.cursor_position()?;
+    Some(pos - Vec2::new(window.width(), window.height()) / 2.)
+}
+
+fn set_zoom(transform: &mut Transform, view: &mut View, zoom: i32) {
+    view.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    let scale = view.scale();
+    transform.scale = Vec3::new(scale, scale, 1.);
+}
+
+/// Zooms with the mouse wheel, keeping the point under the cursor in place.
+fn zoom(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut wheel: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    mut view: ResMut<View>,
+    mut camera: Query<&mut Transform, With<MainCamera>>,
+) {
+    let steps = wheel.iter()
+        .map(|e| match e.unit {
+            MouseScrollUnit::Line => e.y,
+            MouseScrollUnit::Pixel => e.y / 32.,
+        })
+        .sum::<f32>();
+    if steps == 0. || egui_ctx.ctx_mut().is_pointer_over_area() { return; }
+
+    let mut transform = camera.single_mut();
+    let zoom = step(view.zoom, steps.signum() as i32);
+    let before = transform.scale.x;
+    set_zoom(&mut transform, &mut view, zoom);
+    if let Some(offset) = cursor_offset(&windows) {
+        let delta = offset * (before - transform.scale.x);
+        transform.translation += delta.extend(0.);
+    }
+}
+
+/// Pans with a middle click drag, or a left click drag while space is held.
+pub fn pan(
+    mut buttons: EventReader<MouseButtonInput>,
+    mut moves: EventReader<CursorMoved>,
+    mut mouse: ResMut<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mut camera: Query<&mut Transform, With<MainCamera>>,
+    mut panning: Local<Option<MouseButton>>,
+    mut last: Local<Option<Vec2>>,
+) {
+    for event in buttons.iter() {
+        match (event.button, event.state) {
+            (MouseButton::Middle, ElementState::Pressed) => *panning = Some(MouseButton::Middle),
+            (MouseButton::Left, ElementState::Pressed) if keys.pressed(KeyCode::Space) => *panning = Some(MouseButton::Left),
+            (button, ElementState::Released) if *panning == Some(button) => *panning = None,
+            _ => {}
+        }
+    }
+
+    let cursor = moves.iter().last().map(|e| e.position);
+    if let Some(button) = *panning {
+        // Hide the drag from the tools
+        mouse.reset(button);
+        if let (Some(last), Some(cursor)) = (*last, cursor) {
+            let mut transform = camera.single_mut();
+            let delta = (cursor - last) * transform.scale.x;
+            transform.translation -= delta.extend(0.);
+        }
+    }
+    if cursor.is_some() { *last = cursor; }
+}
+
+fn handle_camera_events(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut events: EventReader<CameraEvent>,
+    windows: Res<Windows>,
+    canvas: Res<Canvas>,
+    mut view: ResMut<View>,
+    mut camera: Query<&mut Transform, With<MainCamera>>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    // Part of the window which isn't covered by the side panel, y down
+    let area = egui_ctx.ctx_mut().available_rect();
+    for event in events.iter() {
+        let mut transform = camera.single_mut();
+        let zoom = match event {
+            CameraEvent::Fit => {
+                let tile = canvas.tile_size as f32;
+                let size = Vec2::new(canvas.width as f32, canvas.height as f32) * tile;
+                fit_zoom((area.width() / size.x).min(area.height() / size.y))
+            }
+            CameraEvent::ActualSize => 1,
+        };
+        set_zoom(&mut transform, &mut view, zoom);
+        let offset = Vec2::new(area.center().x - window.width() / 2., window.height() / 2. - area.center().y);
+        let center = canvas.center() + canvas.offset - offset * transform.scale.x;
+        transform.translation = center.extend(transform.translation.z);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_skips_the_unused_levels() {
+        assert_eq!(step(1, 1), 2);
+        assert_eq!(step(1, -1), -2);
+        assert_eq!(step(-2, 1), 1);
+        assert_eq!(step(-2, -1), -3);
+        assert_eq!(step(4, -6), -4);
+        assert_eq!(step(step(3, -5), 5), 3);
+    }
+
+    #[test]
+    fn set_zoom_clamps_and_scales() {
+        let mut transform = Transform::default();
+        let mut view = View { zoom: 1 };
+        set_zoom(&mut transform, &mut view, MAX_ZOOM + 3);
+        assert_eq!(view.zoom, MAX_ZOOM);
+        assert_eq!(transform.scale, Vec3::new(1. / MAX_ZOOM as f32, 1. / MAX_ZOOM as f32, 1.));
+        set_zoom(&mut transform, &mut view, MIN_ZOOM - 3);
+        assert_eq!(view.zoom, MIN_ZOOM);
+        assert_eq!(transform.scale.x, -MIN_ZOOM as f32);
+    }
+
+    #[test]
+    fn fit_zoom_never_overflows_the_area() {
+        assert_eq!(fit_zoom(1.), 1);
+        assert_eq!(fit_zoom(3.9), 3);
+        // Zoomed out, a level shows 1/-zoom of the canvas size
+        assert_eq!(fit_zoom(0.5), -2);
+        assert_eq!(fit_zoom(0.4), -3);
+        for ratio in [0.13, 0.3, 0.99, 1.5, 7.2] {
+            assert!(1. / View { zoom: fit_zoom(ratio) }.scale() <= ratio);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,139 @@
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::renderer::Renderer;
+
+/// Config file read from the working directory when `--config` isn't given.
+pub const DEFAULT_CONFIG: &str = "bevy_textmode.ron";
+
+const USAGE: &str = "Usage: editor [--config FILE] [--tileset FILE] [--palette FILE] [--tile-size N] [--width N] [--height N] [--renderer batched|entities]";
+
+/// Startup settings, read from a RON config file then overridden by CLI arguments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub tileset: String,
+    pub palette: String,
+    pub tile_size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub renderer: Renderer,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tileset: "assets/MRMOTEXT.png".to_string(),
+            palette: "assets/palette.png".to_string(),
+            tile_size: 8,
+            width: 32,
+            height: 18,
+            renderer: Renderer::Batched,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        Config::from_args(std::env::args().skip(1).collect())
+    }
+
+    pub fn from_args(args: Vec<String>) -> Result<Self, String> {
+        let mut config_path = None;
+        let mut overrides = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.as_str() {
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                "--config" | "--tileset" | "--palette" | "--tile-size" | "--width" | "--height" | "--renderer" => args.next()
+                    .ok_or_else(|| format!("Missing value for {}\n{}", arg, USAGE))?,
+                _ => return Err(format!("Unknown argument {}\n{}", arg, USAGE)),
+            };
+            if arg == "--config" { config_path = Some(value); } else { overrides.push((arg, value)); }
+        }
+
+        let mut config = match config_path {
+            Some(path) => Config::load(&path)?,
+            None if Path::new(DEFAULT_CONFIG).exists() => Config::load(DEFAULT_CONFIG)?,
+            None => Config::default(),
+        };
+
+        for (arg, value) in overrides {
+            let number = || value.parse::<u32>().map_err(|_| format!("Invalid value for {}: {}", arg, value));
+            match arg.as_str() {
+                "--tileset" => config.tileset = value.clone(),
+                "--palette" => config.palette = value.clone(),
+                "--tile-size" => config.tile_size = number()?,
+                "--width" => config.width = number()?,
+                "--height" => config.height = number()?,
+                "--renderer" => config.renderer = Renderer::parse(&value)
+                    .ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?,
+                _ => unreachable!(),
+            }
+        }
+
+        if config.tile_size == 0 || config.width == 0 || config.height == 0 {
+            return Err("Tile size and canvas dimensions must be positive".to_string());
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        ron::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Config file with `text` in the temp directory.
+    fn config_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn missing_settings_are_defaults() {
+        let path = config_file("bevy_textmode_defaults.ron", "(width: 40)");
+        let config = Config::from_args(args(&["--config", &path])).unwrap();
+        let default = Config::default();
+        assert_eq!(config.width, 40);
+        assert_eq!((config.tileset, config.palette), (default.tileset, default.palette));
+        assert_eq!((config.tile_size, config.height, config.renderer), (8, 18, Renderer::Batched));
+    }
+
+    #[test]
+    fn arguments_override_the_config_file() {
+        let path = config_file("bevy_textmode_overrides.ron", "(tileset: \"a.png\", tile_size: 16, width: 40)");
+        let config = Config::from_args(args(&[
+            "--tile-size", "12", "--config", &path, "--palette", "p.gpl", "--renderer", "entities",
+        ])).unwrap();
+        assert_eq!((config.tileset.as_str(), config.palette.as_str()), ("a.png", "p.gpl"));
+        assert_eq!((config.tile_size, config.width, config.height), (12, 40, 18));
+        assert_eq!(config.renderer, Renderer::Entities);
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        let path = config_file("bevy_textmode_bad.ron", "(width: -1)");
+        for bad in [
+            &["--width"][..],
+            &["--width", "wide"],
+            &["--height", "0"],
+            &["--renderer", "gpu"],
+            &["--size", "3"],
+            &["--config", &path],
+            &["--config", "missing/bevy_textmode.ron"],
+        ] {
+            assert!(Config::from_args(args(bad)).is_err(), "{:?}", bad);
+        }
+        assert_eq!(Config::from_args(args(&["--help"])).unwrap_err(), USAGE);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,205 @@
+use bevy::prelude::*;
+use bevy_textmode::{BasicMesh, Canvas, Colors, TextModeBundle, TileId, TileMaterial, TilePos, Tiles};
+use crate::MainCamera;
+use crate::gui::{Tool, UiState};
+use crate::selection::Block;
+
+pub(crate) struct CursorPlugin;
+
+impl Plugin for CursorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<HoveredTile>()
+            .add_startup_stage(
+                "game_setup_cursor",
+                SystemStage::single(setup),
+            )
+            .add_system(update_tile)
+            .add_system(update_cursor)
+            .add_system(update_brush.after(update_cursor));
+    }
+}
+
+#[derive(Component)]
+pub struct Cursor;
+
+#[derive(Component)]
+pub struct TileCursor;
+
+/// Cell of a multi-tile brush preview, at `x, y` in the brush.
+#[derive(Component)]
+struct BrushCursor {
+    x: u32,
+    y: u32,
+}
+
+/// Grid cell currently under the mouse, if any.
+#[derive(Default)]
+pub struct HoveredTile {
+    pub pos: Option<TilePos>,
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<TileMaterial>>,
+    tiles: Res<Tiles>,
+    canvas: Res<Canvas>,
+    colors: Res<Colors>,
+    meshes: Res<BasicMesh>,
+) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Default::default(),
+            texture: asset_server.load("cursor.png"),
+            transform: Transform {
+                translation: Vec3::new(0.0, 0.0, 10.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Cursor);
+
+    let mut bundle = TextModeBundle::new(
+        &tiles, &mut materials,
+        &TileId { index: 0, flip: false, rotation: 0 },
+        0, 0,
+        colors.get(0), colors.get(0),
+        meshes.tile.clone(), canvas.as_ref()
+    );
+    // Above the layers, with the previews
+    bundle.transform.translation.z = 1.;
+    commands.spawn_bundle(bundle).insert(TileCursor);
+}
+
+fn update_tile(
+    mut materials: ResMut<Assets<TileMaterial>>,
+    tiles: Res<Tiles>,
+    colors: Res<Colors>,
+    ui_state: Res<UiState>,
+    mut q: Query<(&mut TileId, &Handle<TileMaterial>), With<TileCursor>>,
+) {
+    let (_, handle) = q.single_mut();
+    let tile_material = materials.get_mut(handle).unwrap();
+    tile_material.bg = colors.get(ui_state.bg);
+    tile_material.fg = colors.get(ui_state.fg);
+    tile_material.id = ui_state.tile_id;
+    tile_material.atlas = tiles.atlas.clone();
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_cursor(
+    windows: Res<Windows>,
+    canvas: Res<Canvas>,
+    mut hovered: ResMut<HoveredTile>,
+    mut q: ParamSet<(
+        Query<&Transform, With<MainCamera>>,
+        Query<(&mut Transform, &mut Visibility), With<Cursor>>,
+        Query<(&mut Transform, &mut Visibility), With<TileCursor>>,
+    )>,
+) {
+    let wnd = windows.get_primary().unwrap();
+    if let Some(pos) = wnd.cursor_position() {
+        let size = Vec2::new(wnd.width(), wnd.height());
+        let p = pos - size / 2.0;
+        let pos_wld = q.p0().single().compute_matrix() * p.extend(0.0).extend(1.0);
+
+        let tile = canvas.tile_size as f32;
+        let (x, y) = (pos_wld.x + tile / 2., pos_wld.y + tile / 2.);
+        let display =  x >= canvas.offset.x
+            && x < canvas.width as f32 * tile + canvas.offset.x
+            && y >= canvas.offset.y
+            && y < canvas.height as f32 * tile + canvas.offset.y;
+
+        let x = ((pos_wld.x + tile / 2. - canvas.offset.x) / tile) as i32;
+        let y = ((pos_wld.y + tile / 2. - canvas.offset.y) / tile) as i32;
+
+        let mut query = q.p1();
+        let (_, mut visibility) = query.single_mut();
+        visibility.is_visible = false;
+        // if display {
+        //     cursor_pos.translation.x = x as f32 * tile + canvas.offset.x;
+        //     cursor_pos.translation.y = y as f32 * tile + canvas.offset.y;
+        //     visibility.is_visible = true;
+        // } else {
+        //     visibility.is_visible = false;
+        // }
+
+        hovered.pos = if display { Some(TilePos { x: x as u32, y: y as u32 }) } else { None };
+
+        let mut query = q.p2();
+        let (mut tile_pos, mut visibility) = query.single_mut();
+        if display {
+            tile_pos.translation.x = x as f32 * tile + canvas.offset.x;
+            tile_pos.translation.y = y as f32 * tile + canvas.offset.y;
+            visibility.is_visible = true;
+        } else {
+            visibility.is_visible = false;
+        }
+    } else {
+        hovered.pos = None;
+    }
+}
+
+/// Shows the whole brush instead of [`TileCursor`] when painting with a multi-tile brush.
+#[allow(clippy::too_many_arguments)]
+fn update_brush(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<TileMaterial>>,
+    tiles: Res<Tiles>,
+    colors: Res<Colors>,
+    meshes: Res<BasicMesh>,
+    canvas: Res<Canvas>,
+    ui_state: Res<UiState>,
+    hovered: Res<HoveredTile>,
+    mut shown: Local<Option<Block>>,
+    mut brush_cursors: Query<(Entity, &BrushCursor, &mut Transform, &mut Visibility)>,
+    mut tile_cursor: Query<&mut Visibility, (With<TileCursor>, Without<BrushCursor>)>,
+) {
+    let brush = ui_state.brush.as_ref().filter(|_| ui_state.tool == Tool::Pencil);
+    if brush.is_some() {
+        tile_cursor.single_mut().is_visible = false;
+    }
+
+    if shown.as_ref() != brush || colors.is_changed() || tiles.is_changed() {
+        brush_cursors.iter().for_each(|(e, ..)| commands.entity(e).despawn());
+        *shown = brush.cloned();
+        if let Some(brush) = brush {
+            for y in 0..brush.height {
+                for x in 0..brush.width {
+                    let cell = brush.get(x, y);
+                    if !tiles.contains(&cell.id) { continue; }
+                    let mut bundle = TextModeBundle::new(
+                        &tiles, &mut materials,
+                        &cell.id,
+                        0, 0,
+                        colors.get(cell.bg), colors.get(cell.fg),
+                        meshes.tile.clone(), &canvas
+                    );
+                    bundle.visibility.is_visible = false;
+                    commands.spawn_bundle(bundle).insert(BrushCursor { x, y });
+                }
+            }
+        }
+        return;
+    }
+
+    let brush = match brush {
+        Some(brush) => brush,
+        None => return,
+    };
+    let tile = canvas.tile_size as f32;
+    for (_, cell, mut transform, mut visibility) in brush_cursors.iter_mut() {
+        // The brush is held by its top left cell
+        let pos = hovered.pos.map(|p| (p.x as i32 + cell.x as i32, p.y as i32 - brush.height as i32 + 1 + cell.y as i32));
+        visibility.is_visible = match pos {
+            Some((x, y)) if x < canvas.width as i32 && y >= 0 => {
+                transform.translation.x = x as f32 * tile + canvas.offset.x;
+                transform.translation.y = y as f32 * tile + canvas.offset.y;
+                transform.translation.z = 1.;
+                true
+            }
+            _ => false,
+        };
+    }
+}
\ No newline at end of file
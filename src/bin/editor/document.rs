@@ -0,0 +1,266 @@
+use std::fs;
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use bevy_textmode::{Canvas, Cell, Colors, TileColors, TileId, to_hex, TRANSPARENT};
+use crate::{Grid, NewCanvas};
+use crate::layers::LayerData;
+use crate::palette::{MAX_COLORS, save_path};
+
+/// Version written in saved documents, bumped on breaking format changes.
+pub const DOCUMENT_VERSION: u32 = 2;
+
+pub struct DocumentPlugin;
+
+impl Plugin for DocumentPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CurrentDocument>()
+            .add_event::<DocumentEvent>()
+            .add_system(handle_document_events);
+    }
+}
+
+/// On-disk representation of a canvas.
+#[derive(Serialize, Deserialize)]
+pub struct Document {
+    pub version: u32,
+    pub tileset: String,
+    pub tile_size: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Palette colors as `rrggbb` strings.
+    pub palette: Vec<String>,
+    /// Layers from the bottom.
+    #[serde(default)]
+    pub layers: Vec<LayerData>,
+    /// Cells of version 1 documents, which had a single layer.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cells: Vec<Cell>,
+}
+
+impl Document {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        let mut document: Document = ron::from_str(&text).map_err(|e| format!("Invalid document {}: {}", path, e))?;
+        if document.version > DOCUMENT_VERSION {
+            return Err(format!("Unsupported document version {}", document.version));
+        }
+        if document.version < 2 {
+            let cells = std::mem::take(&mut document.cells);
+            document.layers = vec![LayerData::new("Background", cells)];
+        }
+        if document.layers.is_empty() {
+            return Err("Document has no layers".to_string());
+        }
+        if document.palette.len() > MAX_COLORS {
+            return Err(format!("Too many colors ({}, at most {})", document.palette.len(), MAX_COLORS));
+        }
+        let colors = document.palette.len();
+        for layer in &document.layers {
+            if layer.cells.len() != (document.width * document.height) as usize {
+                return Err(format!(
+                    "Expected {} cells in layer {}, found {}",
+                    document.width * document.height, layer.name, layer.cells.len()
+                ));
+            }
+            let used = layer.cells.iter()
+                .flat_map(|cell| [cell.fg, cell.bg])
+                .filter(|&i| i != TRANSPARENT)
+                .max();
+            if let Some(i) = used.filter(|&i| i >= colors) {
+                return Err(format!("Layer {} uses color #{} but the palette has {} colors", layer.name, i, colors));
+            }
+        }
+        Ok(document)
+    }
+
+    pub fn colors(&self) -> Result<Colors, String> {
+        self.palette.iter()
+            .map(|hex| Color::hex(hex).map_err(|_| format!("Invalid palette color {}", hex)))
+            .collect::<Result<Vec<Color>, String>>()
+            .and_then(Colors::new)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, PrettyConfig::new().depth_limit(4))
+            .map_err(|e| format!("Couldn't serialize document: {}", e))?;
+        fs::write(path, text).map_err(|e| format!("Couldn't write {}: {}", path, e))
+    }
+}
+
+/// File actions triggered from the GUI.
+pub enum DocumentEvent {
+    Save,
+    SaveAs(String),
+    Open(String),
+}
+
+/// Path of the open document and the result of the last file action.
+#[derive(Default)]
+pub struct CurrentDocument {
+    pub path: Option<String>,
+    pub path_input: String,
+    pub status: Option<String>,
+}
+
+fn handle_document_events(
+    mut events: EventReader<DocumentEvent>,
+    mut document: ResMut<CurrentDocument>,
+    mut new_canvas: EventWriter<NewCanvas>,
+    canvas: Res<Canvas>,
+    grid: Res<Grid>,
+    colors: Res<Colors>,
+    cells: Query<(&TileId, &TileColors)>,
+) {
+    for event in events.iter() {
+        let path = match event {
+            DocumentEvent::Save => match &document.path {
+                Some(path) => path.clone(),
+                None => document.path_input.clone(),
+            },
+            DocumentEvent::SaveAs(path) | DocumentEvent::Open(path) => path.clone(),
+        };
+        if path.is_empty() {
+            document.status = Some("No file selected".to_string());
+            continue;
+        }
+
+        match event {
+            DocumentEvent::Save | DocumentEvent::SaveAs(_) => match save(&path, &canvas, &grid, &colors, &cells) {
+                Ok(_) => {
+                    document.status = Some(format!("Saved {}", path));
+                    document.path = Some(path.clone());
+                    document.path_input = path;
+                }
+                Err(error) => document.status = Some(error),
+            },
+            // The path and palette only change once the canvas accepts the document, the palette
+            // being saved next to it
+            DocumentEvent::Open(_) => match Document::load(&path).and_then(|loaded| Ok((loaded.colors()?, loaded))) {
+                Ok((palette, loaded)) => new_canvas.send(NewCanvas {
+                    tileset: loaded.tileset,
+                    tile_size: loaded.tile_size,
+                    width: loaded.width,
+                    height: loaded.height,
+                    layers: Some(loaded.layers),
+                    palette: Some((palette, save_path(&path))),
+                    status: format!("Opened {}", path),
+                    path: Some(path),
+                }),
+                Err(error) => document.status = Some(error),
+            },
+        }
+    }
+}
+
+fn save(
+    path: &str,
+    canvas: &Canvas,
+    grid: &Grid,
+    colors: &Colors,
+    cells: &Query<(&TileId, &TileColors)>,
+) -> Result<(), String> {
+    let layers = grid.read_layers(cells).ok_or_else(|| "Canvas isn't ready".to_string())?;
+
+    Document {
+        version: DOCUMENT_VERSION,
+        tileset: canvas.tileset.clone(),
+        tile_size: canvas.tile_size,
+        width: canvas.width,
+        height: canvas.height,
+        palette: colors.iter().map(|&c| to_hex(c)).collect(),
+        layers,
+        cells: vec![],
+    }.save(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(index: usize, fg: usize, bg: usize) -> Cell {
+        Cell { id: TileId { index, ..TileId::new() }, fg, bg }
+    }
+
+    fn document() -> Document {
+        let mut upper = LayerData::new("Upper", vec![Cell::transparent(), cell(3, 2, TRANSPARENT)]);
+        upper.opacity = 0.5;
+        Document {
+            version: DOCUMENT_VERSION,
+            tileset: "assets/tileset.png".to_string(),
+            tile_size: 8,
+            width: 2,
+            height: 1,
+            palette: vec!["000000".to_string(), "ff8000".to_string(), "123456".to_string()],
+            layers: vec![LayerData::new("Background", vec![cell(1, 1, 0), cell(2, 0, 2)]), upper],
+            cells: vec![],
+        }
+    }
+
+    /// Path of a document named `name` in the temp directory.
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(name).to_str().unwrap().to_string()
+    }
+
+    /// Writes `text` to a temp file then loads it as a document.
+    fn load_text(name: &str, text: &str) -> Result<Document, String> {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        Document::load(&path)
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("bevy_textmode_round_trip.ron");
+        document().save(&path).unwrap();
+        let loaded = Document::load(&path).unwrap();
+        assert_eq!((loaded.version, loaded.tileset.as_str()), (DOCUMENT_VERSION, "assets/tileset.png"));
+        assert_eq!((loaded.tile_size, loaded.width, loaded.height), (8, 2, 1));
+        assert_eq!(loaded.palette, document().palette);
+        assert_eq!(loaded.colors().unwrap().get(1), Color::rgb_u8(255, 128, 0));
+        for (loaded, saved) in loaded.layers.iter().zip(&document().layers) {
+            assert_eq!((&loaded.name, loaded.visible, loaded.locked), (&saved.name, saved.visible, saved.locked));
+            assert_eq!((loaded.opacity, &loaded.cells), (saved.opacity, &saved.cells));
+        }
+        assert_eq!(loaded.layers.len(), 2);
+    }
+
+    #[test]
+    fn version_1_cells_become_a_layer() {
+        let loaded = load_text("bevy_textmode_v1.ron", r#"(
+            version: 1,
+            tileset: "assets/tileset.png",
+            tile_size: 8,
+            width: 2,
+            height: 1,
+            palette: ["000000", "ffffff"],
+            cells: [
+                (id: (index: 1, flip: false, rotation: 0), fg: 1, bg: 0),
+                (id: (index: 2, flip: true, rotation: 3), fg: 0, bg: 1),
+            ],
+        )"#).unwrap();
+        assert_eq!(loaded.layers.len(), 1);
+        assert_eq!(loaded.layers[0].name, "Background");
+        assert_eq!(loaded.layers[0].cells[1], Cell { id: TileId { index: 2, flip: true, rotation: 3 }, fg: 0, bg: 1 });
+        assert!(loaded.cells.is_empty());
+    }
+
+    #[test]
+    fn wrong_cell_counts_are_rejected() {
+        let mut document = document();
+        document.layers[1].cells.pop();
+        let path = temp_path("bevy_textmode_cell_count.ron");
+        document.save(&path).unwrap();
+        assert_eq!(Document::load(&path).err().unwrap(), "Expected 2 cells in layer Upper, found 1");
+    }
+
+    #[test]
+    fn palette_indices_must_exist() {
+        let mut document = document();
+        document.layers[0].cells[1].bg = 3;
+        let path = temp_path("bevy_textmode_palette_index.ron");
+        document.save(&path).unwrap();
+        assert_eq!(Document::load(&path).err().unwrap(), "Layer Background uses color #3 but the palette has 3 colors");
+    }
+}
\ No newline at end of file
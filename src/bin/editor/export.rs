@@ -0,0 +1,169 @@
+use bevy::prelude::*;
+use image::{Rgba, RgbaImage};
+use bevy_textmode::{Canvas, Colors, TileColors, TileId, Tiles};
+use crate::Grid;
+use crate::layers::LayerData;
+
+/// Integer scales offered for PNG export.
+pub const EXPORT_SCALES: [u32; 4] = [1, 2, 4, 8];
+
+pub struct ExportPlugin;
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ExportSettings {
+                path: "export.png".to_string(),
+                scale: 1,
+                status: None,
+            })
+            .add_event::<ExportEvent>()
+            .add_system(export);
+    }
+}
+
+pub struct ExportSettings {
+    pub path: String,
+    pub scale: u32,
+    pub status: Option<String>,
+}
+
+/// Writes the canvas to `ExportSettings.path`.
+pub struct ExportEvent;
+
+fn export(
+    mut events: EventReader<ExportEvent>,
+    mut settings: ResMut<ExportSettings>,
+    canvas: Res<Canvas>,
+    grid: Res<Grid>,
+    tiles: Res<Tiles>,
+    colors: Res<Colors>,
+    cells: Query<(&TileId, &TileColors)>,
+) {
+    for _ in events.iter() {
+        let result = grid.read_layers(&cells)
+            .ok_or_else(|| "Canvas isn't ready".to_string())
+            .and_then(|layers| rasterize(&canvas, &layers, &tiles, &colors, settings.scale))
+            .and_then(|img| img.save(&settings.path).map_err(|e| format!("Couldn't write {}: {}", settings.path, e)));
+        settings.status = Some(match result {
+            Ok(_) => format!("Exported {}", settings.path),
+            Err(error) => error,
+        });
+    }
+}
+
+/// Draws the visible `layers` on the CPU, each tile pixel being `scale`×`scale` pixels.
+pub fn rasterize(
+    canvas: &Canvas,
+    layers: &[LayerData],
+    tiles: &Tiles,
+    colors: &Colors,
+    scale: u32,
+) -> Result<RgbaImage, String> {
+    let size = canvas.tile_size;
+    let mut img = RgbaImage::new(canvas.width * size * scale, canvas.height * size * scale);
+
+    for layer in layers.iter().filter(|layer| layer.visible) {
+        for (i, cell) in layer.cells.iter().enumerate() {
+            if !tiles.contains(&cell.id) { return Err(format!("Tile {} isn't in the tileset", cell.id.index)); }
+            let fg = colors.faded(cell.fg, layer.opacity);
+            let bg = colors.faded(cell.bg, layer.opacity);
+            let x0 = (i as u32 % canvas.width) * size;
+            // The grid's y axis points up, the image's points down
+            let y0 = (canvas.height - 1 - i as u32 / canvas.width) * size;
+
+            for y in 0..size {
+                for x in 0..size {
+                    let color = if tiles.lit(&cell.id, x, y) { fg } else { bg };
+                    if color.a() == 0. { continue; }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            let pixel = img.get_pixel_mut((x0 + x) * scale + dx, (y0 + y) * scale + dy);
+                            *pixel = blend(color, *pixel);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(img)
+}
+
+fn to_rgba(color: Color) -> Rgba<u8> {
+    Rgba(color.as_rgba_f32().map(|c| (c * 255.).round() as u8))
+}
+
+/// Draws `color` over `below` with the "over" operator, in linear space like the GPU does when
+/// drawing the canvas to the sRGB window.
+fn blend(color: Color, below: Rgba<u8>) -> Rgba<u8> {
+    let [r, g, b, a] = color.as_linear_rgba_f32();
+    let [br, bg, bb, ba] = Color::rgba_u8(below[0], below[1], below[2], below[3]).as_linear_rgba_f32();
+    let alpha = a + ba * (1. - a);
+    if alpha == 0. { return below; }
+    let mix = |c: f32, bc: f32| (c * a + bc * ba * (1. - a)) / alpha;
+    to_rgba(Color::rgba_linear(mix(r, br), mix(g, bg), mix(b, bb), alpha))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::AssetPlugin;
+    use bevy::core::CorePlugin;
+    use bevy_textmode::{Cell, init_spritesheet};
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /// Two 2px glyphs, the first one only lit at its top left pixel.
+    fn tiles() -> Tiles {
+        let path = std::env::temp_dir().join("bevy_textmode_export_tiles.png");
+        let mut img = RgbaImage::from_pixel(4, 2, BLACK);
+        img.put_pixel(0, 0, WHITE);
+        img.save(&path).unwrap();
+
+        let mut app = App::new();
+        app.add_plugin(CorePlugin).add_plugin(AssetPlugin).add_asset::<Image>();
+        let mut images = app.world.resource_mut::<Assets<Image>>();
+        init_spritesheet(path.to_str().unwrap(), 2, &mut images).unwrap()
+    }
+
+    fn cell(index: usize, flip: bool, rotation: u8, bg: usize) -> Cell {
+        Cell { id: TileId { index, flip, rotation }, fg: 1, bg }
+    }
+
+    #[test]
+    fn rasterize_scales_and_transforms_glyphs() {
+        let canvas = Canvas {
+            tileset: String::new(),
+            palette: String::new(),
+            tile_size: 2,
+            width: 2,
+            height: 2,
+            offset: Vec2::ZERO,
+        };
+        let colors = Colors::new(vec![Color::BLACK, Color::WHITE, Color::rgb_u8(255, 0, 0)]).unwrap();
+        // Bottom row first
+        let cells = vec![cell(0, false, 0, 0), cell(0, true, 0, 0), cell(0, false, 1, 0), cell(1, false, 0, 2)];
+        let img = rasterize(&canvas, &[LayerData::new("Background", cells)], &tiles(), &colors, 2).unwrap();
+
+        assert_eq!(img.dimensions(), (8, 8));
+        let lit = |x0: u32, y0: u32| (0..4).flat_map(|y| (0..4).map(move |x| (x, y)))
+            .filter(|&(x, y)| img[(x0 + x, y0 + y)] == WHITE)
+            .collect::<Vec<(u32, u32)>>();
+        assert_eq!(lit(0, 4), vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+        assert_eq!(lit(4, 4), vec![(2, 0), (3, 0), (2, 1), (3, 1)]);
+        // A quarter turn counterclockwise brings the top left pixel to the bottom left
+        assert_eq!(lit(0, 0), vec![(0, 2), (1, 2), (0, 3), (1, 3)]);
+        assert!(lit(4, 0).is_empty());
+        assert_eq!(img[(5, 1)], Rgba([255, 0, 0, 255]));
+        assert_eq!(img[(2, 6)], BLACK);
+    }
+
+    #[test]
+    fn blend_mixes_in_linear_space() {
+        assert_eq!(blend(Color::rgba(1., 1., 1., 0.5), BLACK), Rgba([188, 188, 188, 255]));
+        assert_eq!(blend(Color::rgba(1., 0., 0., 1.), WHITE), Rgba([255, 0, 0, 255]));
+        assert_eq!(blend(Color::rgba(1., 1., 1., 0.), Rgba([0, 0, 0, 0])), Rgba([0, 0, 0, 0]));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,52 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_textmode::{TileColors, TileId};
+use crate::Grid;
+use crate::cursor::{HoveredTile, update_cursor};
+use crate::gui::{Tool, UiState};
+
+pub struct EyedropperPlugin;
+
+impl Plugin for EyedropperPlugin {
+    fn build(&self, app: &mut App) {
+        // Runs once the hovered cell is known, the tools run after it so an Alt+click can be
+        // hidden from them
+        app.add_system(pick.after(update_cursor));
+    }
+}
+
+/// What the eyedropper reads from the clicked cell.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum PickMode {
+    Glyph,
+    Colors,
+    All,
+}
+
+pub fn pick(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut mouse: ResMut<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    hovered: Res<HoveredTile>,
+    grid: Res<Grid>,
+    cells: Query<(&TileId, &TileColors)>,
+    mut ui_state: ResMut<UiState>,
+) {
+    let alt = keys.any_pressed([KeyCode::LAlt, KeyCode::RAlt]);
+    if ui_state.tool != Tool::Eyedropper && !alt { return; }
+    if !mouse.just_pressed(MouseButton::Left) || egui_ctx.ctx_mut().is_pointer_over_area() { return; }
+    if alt { mouse.reset(MouseButton::Left); }
+
+    let (id, colors) = match hovered.pos.and_then(|pos| grid.get(&pos)).and_then(|e| cells.get(e).ok()) {
+        Some(cell) => cell,
+        None => return,
+    };
+    if ui_state.pick != PickMode::Colors {
+        ui_state.tile_id = *id;
+        ui_state.brush = None;
+    }
+    if ui_state.pick != PickMode::Glyph {
+        ui_state.fg = colors.fg;
+        ui_state.bg = colors.bg;
+    }
+}
\ No newline at end of file
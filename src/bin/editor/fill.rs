@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_textmode::{Canvas, Cell, TileColors, TileId, TilePos};
+use crate::Grid;
+use crate::cursor::HoveredTile;
+use crate::eyedropper::pick;
+use crate::gui::{Tool, UiState};
+use crate::history::History;
+use crate::paint::{apply_paint, PaintEvent};
+
+pub struct FillPlugin;
+
+impl Plugin for FillPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(fill.after(pick).before(apply_paint));
+    }
+}
+
+/// Which parts of a cell must match the clicked one to be filled.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum FillMatch {
+    Glyph,
+    Colors,
+    All,
+}
+
+impl FillMatch {
+    fn matches(&self, a: &Cell, b: &Cell) -> bool {
+        match self {
+            FillMatch::Glyph => a.id == b.id,
+            FillMatch::Colors => a.fg == b.fg && a.bg == b.bg,
+            FillMatch::All => a == b,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn fill(
+    mut egui_ctx: ResMut<EguiContext>,
+    mouse: Res<Input<MouseButton>>,
+    hovered: Res<HoveredTile>,
+    ui_state: Res<UiState>,
+    canvas: Res<Canvas>,
+    grid: Res<Grid>,
+    cells: Query<(&TileId, &TileColors)>,
+    mut history: ResMut<History>,
+    mut events: EventWriter<PaintEvent>,
+) {
+    if ui_state.tool != Tool::Fill || !mouse.just_pressed(MouseButton::Left) { return; }
+    if egui_ctx.ctx_mut().is_pointer_over_area() { return; }
+    let (start, cells) = match (hovered.pos, grid.read(&cells)) {
+        (Some(start), Some(cells)) => (start, cells),
+        _ => return,
+    };
+
+    let new = Cell { id: ui_state.tile_id, fg: ui_state.fg, bg: ui_state.bg };
+    let region = flood(&canvas, &cells, start, ui_state.fill_match, ui_state.fill_diagonal);
+    history.name("Fill");
+    for pos in region {
+        events.send(PaintEvent { pos, cell: new });
+    }
+}
+
+/// Cells connected to `start` which match it according to `mode`.
+pub fn flood(canvas: &Canvas, cells: &[Cell], start: TilePos, mode: FillMatch, diagonal: bool) -> Vec<TilePos> {
+    let (w, h) = (canvas.width as i32, canvas.height as i32);
+    let index = |x: i32, y: i32| (x + y * w) as usize;
+    let target = cells[index(start.x as i32, start.y as i32)];
+
+    let mut visited = vec![false; cells.len()];
+    let mut queue = VecDeque::from([(start.x as i32, start.y as i32)]);
+    let mut region = vec![];
+    visited[index(start.x as i32, start.y as i32)] = true;
+
+    let straight = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+    let diagonals = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+    while let Some((x, y)) = queue.pop_front() {
+        region.push(TilePos { x: x as u32, y: y as u32 });
+        let neighbors = straight.iter().chain(if diagonal { diagonals.iter() } else { [].iter() });
+        for (dx, dy) in neighbors {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= w || ny >= h || visited[index(nx, ny)] { continue; }
+            visited[index(nx, ny)] = true;
+            if mode.matches(&cells[index(nx, ny)], &target) {
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+    region
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cells of a grid drawn with one character per cell, bottom row last.
+    fn grid(rows: &[&str]) -> (Canvas, Vec<Cell>) {
+        let canvas = Canvas {
+            tileset: String::new(),
+            palette: String::new(),
+            tile_size: 8,
+            width: rows[0].len() as u32,
+            height: rows.len() as u32,
+            offset: Vec2::ZERO,
+        };
+        let cells = rows.iter().rev()
+            .flat_map(|row| row.bytes())
+            .map(|c| Cell { id: TileId { index: (c == b'#') as usize, ..TileId::new() }, fg: (c == b'o') as usize, bg: 0 })
+            .collect();
+        (canvas, cells)
+    }
+
+    fn filled(canvas: &Canvas, cells: &[Cell], mode: FillMatch, diagonal: bool) -> Vec<(u32, u32)> {
+        let mut region = flood(canvas, cells, TilePos { x: 0, y: 0 }, mode, diagonal).iter()
+            .map(|p| (p.x, p.y))
+            .collect::<Vec<(u32, u32)>>();
+        region.sort_unstable();
+        region
+    }
+
+    #[test]
+    fn walls_stop_the_fill() {
+        let (canvas, cells) = grid(&[
+            "..#.",
+            ".#..",
+            "..#.",
+        ]);
+        assert_eq!(filled(&canvas, &cells, FillMatch::Glyph, false), vec![(0, 0), (0, 1), (0, 2), (1, 0), (1, 2)]);
+        assert_eq!(filled(&canvas, &cells, FillMatch::Glyph, true).len(), 9);
+    }
+
+    #[test]
+    fn match_modes() {
+        let (canvas, cells) = grid(&["..o#"]);
+        assert_eq!(filled(&canvas, &cells, FillMatch::Glyph, false).len(), 3);
+        assert_eq!(filled(&canvas, &cells, FillMatch::Colors, false).len(), 2);
+        assert_eq!(filled(&canvas, &cells, FillMatch::All, false).len(), 2);
+    }
+}
\ No newline at end of file
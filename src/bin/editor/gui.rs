@@ -0,0 +1,1008 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext, EguiPlugin};
+use bevy_egui::egui::color_picker::{Alpha, color_edit_button_srgba};
+use bevy_egui::egui::{Color32, FontData, FontDefinitions, FontFamily, Pos2, TextEdit};
+use egui_extras::RetainedImage;
+use bevy_textmode::{Canvas, Colors, TileId, Tiles, to_hex, TRANSPARENT};
+use crate::{Grid, NewCanvas};
+use crate::camera::{CameraEvent, View};
+use crate::overlay::{Guide, Overlay};
+use crate::box_drawing::{BoxGlyphs, BoxStyle, MASK_LABELS, SaveBoxGlyphs};
+use crate::document::{CurrentDocument, DocumentEvent};
+use crate::export::{EXPORT_SCALES, ExportEvent, ExportSettings};
+use crate::import::{ImportEvent, ImportSettings};
+use crate::eyedropper::PickMode;
+use crate::fill::FillMatch;
+use crate::history::{History, HistoryEvent};
+use crate::layers::LayerEvent;
+use crate::palette::{PaletteEvent, PaletteFile, save_path};
+use crate::selection::{Block, Selection, SelectionEvent};
+use crate::text::{CharMap, CharPreset, SaveCharMap};
+
+pub struct GuiPlugin;
+
+impl Plugin for GuiPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugin(EguiPlugin)
+            .init_resource::<UiState>()
+            .add_startup_system(setup)
+            .add_system(load_tileset)
+            .add_system(ui.after(load_tileset))
+            .add_system(layers_window.after(ui))
+            .add_system(import_window.after(ui));
+    }
+}
+
+pub struct UiState {
+    /// Tileset sheet in the fg/bg colors `tint`.
+    image: Option<RetainedImage>,
+    tile: Option<egui::Image>,
+    /// Tileset path and tile size `sheet` was loaded with.
+    tileset: Option<(String, u32)>,
+    sheet: Option<image::RgbaImage>,
+    tint: Option<[[u8; 4]; 2]>,
+    picker_open: bool,
+    picker_zoom: f32,
+    layers_open: bool,
+    /// Palette entry shown in the color editor.
+    palette_entry: usize,
+    hex_input: String,
+    /// Guide added by the ADD button of the View section.
+    new_guide: Guide,
+    /// Character and glyph of the SET button of the text tool.
+    char_input: String,
+    char_glyph: usize,
+    new_canvas: NewCanvasDialog,
+    pub tool: Tool,
+    pub tile_id: TileId,
+    /// Block of cells painted by the pencil instead of `tile_id`.
+    pub brush: Option<Block>,
+    /// Size of the brushes taken from the tileset.
+    tileset_brush: [u32; 2],
+    pub fg: usize,
+    pub bg: usize,
+    pub fill_match: FillMatch,
+    pub fill_diagonal: bool,
+    pub shape_filled: bool,
+    pub box_style: BoxStyle,
+    /// Draw boxes by dragging a rectangle instead of following the pointer.
+    pub box_rectangle: bool,
+    pub pick: PickMode,
+}
+
+/// Action of the left mouse button on the canvas.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum Tool {
+    Pencil,
+    Fill,
+    Line,
+    Rectangle,
+    Ellipse,
+    Box,
+    Select,
+    Eyedropper,
+    Text,
+}
+
+impl Default for UiState {
+    fn default() -> Self {
+        UiState {
+            image: None,
+            tile: None,
+            tileset: None,
+            sheet: None,
+            tint: None,
+            picker_open: true,
+            picker_zoom: 2.,
+            layers_open: true,
+            palette_entry: 0,
+            hex_input: String::new(),
+            new_guide: Guide { vertical: true, pos: 0 },
+            char_input: String::new(),
+            char_glyph: 0,
+            new_canvas: NewCanvasDialog::default(),
+            tool: Tool::Pencil,
+            tile_id: TileId::new(),
+            brush: None,
+            tileset_brush: [2, 2],
+            fg: 10,
+            bg: 0,
+            fill_match: FillMatch::All,
+            fill_diagonal: false,
+            shape_filled: false,
+            box_style: BoxStyle::Single,
+            box_rectangle: false,
+            pick: PickMode::All,
+        }
+    }
+}
+
+/// Fields of the "New canvas" window.
+#[derive(Default)]
+struct NewCanvasDialog {
+    open: bool,
+    tileset: String,
+    tile_size: u32,
+    width: u32,
+    height: u32,
+}
+
+fn setup(
+    mut egui_ctx: ResMut<EguiContext>,
+) {
+    let mut fonts = FontDefinitions::default();
+    fonts.font_data
+        .insert("JB Mono".to_owned(),
+                FontData::from_static(include_bytes!("../../../assets/JetBrainsMono-Regular.ttf"))
+        );
+    [&FontFamily::Proportional, &FontFamily::Monospace].iter().for_each(|key| {
+        fonts.families
+            .get_mut(key).unwrap()
+            .insert(0, "JB Mono".to_owned());
+    });
+
+    egui_ctx.ctx_mut().set_fonts(fonts);
+}
+
+/// Reloads the tileset preview when the canvas tileset changes, and recolors it with the current fg/bg.
+fn load_tileset(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut ui_state: ResMut<UiState>,
+    canvas: Res<Canvas>,
+    tiles: Res<Tiles>,
+    colors: Res<Colors>,
+) {
+    let tileset = (canvas.tileset.clone(), canvas.tile_size);
+    if ui_state.tileset.as_ref() != Some(&tileset) {
+        ui_state.sheet = image::open(&canvas.tileset).ok().map(|image| image.to_rgba8());
+        ui_state.tile_id.index = ui_state.tile_id.index.min(tiles.count() - 1);
+        ui_state.tileset = Some(tileset);
+        ui_state.tint = None;
+    }
+
+    let rgba = |c: Color| c.as_rgba_f32().map(|c| (c * 255.).round() as u8);
+    let tint = [rgba(colors.get(ui_state.fg)), rgba(colors.get(ui_state.bg))];
+    if ui_state.tint == Some(tint) { return; }
+    ui_state.tint = Some(tint);
+
+    let image = ui_state.sheet.as_ref().map(|sheet| {
+        // Same convention as the canvas: black pixels are background
+        let pixels = sheet.pixels()
+            .flat_map(|p| if matches!(p.0, [0, 0, 0, _]) { tint[1] } else { tint[0] })
+            .collect::<Vec<u8>>();
+        let size = [sheet.width() as usize, sheet.height() as usize];
+        RetainedImage::from_color_image("tileset", egui::ColorImage::from_rgba_unmultiplied(size, &pixels))
+    });
+    ui_state.tile = image.as_ref().map(|image| egui::Image::new(
+        image.texture_id(egui_ctx.ctx_mut()),
+        egui::vec2((canvas.tile_size * 4) as f32, (canvas.tile_size * 4) as f32)
+    ));
+    ui_state.image = image;
+}
+
+/// Files read and written from the side panel.
+#[derive(SystemParam)]
+pub struct Files<'w, 's> {
+    document: ResMut<'w, CurrentDocument>,
+    document_events: EventWriter<'w, 's, DocumentEvent>,
+    export: ResMut<'w, ExportSettings>,
+    export_events: EventWriter<'w, 's, ExportEvent>,
+    import: ResMut<'w, ImportSettings>,
+    palette: ResMut<'w, PaletteFile>,
+    palette_events: EventWriter<'w, 's, PaletteEvent>,
+    box_glyphs: ResMut<'w, BoxGlyphs>,
+    box_glyphs_events: EventWriter<'w, 's, SaveBoxGlyphs>,
+    char_map: ResMut<'w, CharMap>,
+    char_map_events: EventWriter<'w, 's, SaveCharMap>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn ui(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut colors: ResMut<Colors>,
+    canvas: Res<Canvas>,
+    tiles: Res<Tiles>,
+    mut ui_state: ResMut<UiState>,
+    mut new_canvas: EventWriter<NewCanvas>,
+    mut files: Files,
+    mut history: ResMut<History>,
+    mut history_events: EventWriter<HistoryEvent>,
+    selection: Res<Selection>,
+    mut selection_events: EventWriter<SelectionEvent>,
+    view: Res<View>,
+    mut camera_events: EventWriter<CameraEvent>,
+    mut overlay: ResMut<Overlay>,
+) {
+    egui::SidePanel::left("side_panel")
+        .default_width(200.0)
+        .resizable(false)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            ui.add_space(16.);
+
+            ui.horizontal(|ui| {
+                ui.centered_and_justified(|ui| ui.heading("IMPERATRICES"));
+            });
+
+            ui.add_space(8.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.add(TextEdit::singleline(&mut files.document.path_input).desired_width(168.).hint_text("file.ron"));
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                if ui.button("NEW").clicked() {
+                    ui_state.new_canvas = NewCanvasDialog {
+                        open: true,
+                        tileset: canvas.tileset.clone(),
+                        tile_size: canvas.tile_size,
+                        width: canvas.width,
+                        height: canvas.height,
+                    };
+                }
+                ui.add_space(4.);
+                if ui.button("SAVE").clicked() { files.document_events.send(DocumentEvent::Save); }
+                ui.add_space(4.);
+                if ui.button("SAVE AS").clicked() { files.document_events.send(DocumentEvent::SaveAs(files.document.path_input.clone())); }
+                ui.add_space(4.);
+                if ui.button("OPEN").clicked() { files.document_events.send(DocumentEvent::Open(files.document.path_input.clone())); }
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                if ui.button("IMPORT IMAGE").on_hover_text("Convert a PNG to a new canvas").clicked() { files.import.open = true; }
+            });
+
+            if let Some(status) = &files.document.status {
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.label(status);
+                });
+            }
+
+            ui.add_space(8.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.add(TextEdit::singleline(&mut files.export.path).desired_width(168.).hint_text("export.png"));
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                for scale in EXPORT_SCALES {
+                    ui.selectable_value(&mut files.export.scale, scale, format!("{}x", scale));
+                }
+                ui.add_space(4.);
+                if ui.button("EXPORT").clicked() { files.export_events.send(ExportEvent); }
+            });
+
+            if let Some(status) = &files.export.status {
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.label(status);
+                });
+            }
+
+            ui.add_space(8.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.add(TextEdit::singleline(&mut files.palette.path).desired_width(168.).hint_text("palette.gpl"));
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                if ui.button("IMPORT").clicked() { files.palette_events.send(PaletteEvent::Import); }
+                ui.add_space(4.);
+                if ui.button("EXPORT").clicked() { files.palette_events.send(PaletteEvent::Export); }
+                ui.add_space(4.);
+                if ui.button("SAVE").on_hover_text(save_path(&canvas.palette)).clicked() { files.palette_events.send(PaletteEvent::Save); }
+            });
+
+            if let Some(status) = &files.palette.status {
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.label(status);
+                });
+            }
+
+            ui.add_space(8.);
+
+            if ui_state.fg != TRANSPARENT { ui_state.fg = ui_state.fg.min(colors.len() - 1); }
+            if ui_state.bg != TRANSPARENT { ui_state.bg = ui_state.bg.min(colors.len() - 1); }
+            ui_state.palette_entry = ui_state.palette_entry.min(colors.len() - 1);
+
+            egui::ScrollArea::vertical().id_source("palette").max_height(352.).show(ui, |ui| {
+                for i in 0..colors.len() {
+                    ui.horizontal(|ui| {
+                        ui.add_space(16.);
+                        ui.selectable_value(&mut ui_state.palette_entry, i, format!("{:>3}", i));
+                        let [r, g, b, _] = colors.get(i).as_rgba_f32().map(|c| (c * 255.).round() as u8);
+                        let mut color = Color32::from_rgb(r, g, b);
+                        let interact_size = std::mem::replace(&mut ui.spacing_mut().interact_size, egui::vec2(40., 20.));
+                        // Cells keep their palette index, so editing an entry recolors the canvas
+                        if color_edit_button_srgba(ui, &mut color, Alpha::Opaque).changed() {
+                            colors.set(i, Color::rgb_u8(color.r(), color.g(), color.b()));
+                        }
+                        ui.spacing_mut().interact_size = interact_size;
+                        ui.add_space(8.);
+                        if ui.button("-BG-").clicked() { ui_state.bg = i; }
+                        ui.add_space(4.);
+                        if ui.button("-FG-").clicked() { ui_state.fg = i; }
+                    });
+                    ui.add_space(2.);
+                }
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.label("Transparent").on_hover_text("Shows the layers below");
+                ui.add_space(8.);
+                if ui.button("-BG-").clicked() { ui_state.bg = TRANSPARENT; }
+                ui.add_space(4.);
+                if ui.button("-FG-").clicked() { ui_state.fg = TRANSPARENT; }
+            });
+
+            ui.add_space(4.);
+
+            let entry = ui_state.palette_entry;
+            let [mut r, mut g, mut b, _] = colors.get(entry).as_rgba_f32().map(|c| (c * 255.).round() as u8);
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.label(format!("#{}", entry));
+                let response = ui.add(TextEdit::singleline(&mut ui_state.hex_input).desired_width(56.));
+                if response.changed() {
+                    let hex = ui_state.hex_input.trim_start_matches('#');
+                    if let (6, Ok(color)) = (hex.len(), Color::hex(hex)) { colors.set(entry, color); }
+                }
+                if !response.has_focus() { ui_state.hex_input = to_hex(colors.get(entry)); }
+                let rgb = [ui.add(egui::DragValue::new(&mut r)), ui.add(egui::DragValue::new(&mut g)), ui.add(egui::DragValue::new(&mut b))];
+                if rgb.iter().any(|response| response.changed()) { colors.set(entry, Color::rgb_u8(r, g, b)); }
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                if ui.button("ADD").clicked() { files.palette_events.send(PaletteEvent::Add(entry)); }
+                ui.add_space(4.);
+                if ui.button("REMOVE").clicked() { files.palette_events.send(PaletteEvent::Remove(entry)); }
+                ui.add_space(4.);
+                if ui.add_enabled(entry > 0, egui::Button::new("UP")).clicked() {
+                    files.palette_events.send(PaletteEvent::Swap(entry, entry - 1));
+                    ui_state.palette_entry -= 1;
+                }
+                ui.add_space(4.);
+                if ui.add_enabled(entry + 1 < colors.len(), egui::Button::new("DOWN")).clicked() {
+                    files.palette_events.send(PaletteEvent::Swap(entry, entry + 1));
+                    ui_state.palette_entry += 1;
+                }
+            });
+
+            ui.add_space(16.);
+
+            ui.horizontal(|ui| {
+                ui.centered_and_justified(|ui| ui.heading("Tools"));
+            });
+
+            ui.add_space(8.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Pencil, "PENCIL");
+                ui.add_space(4.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Fill, "FILL");
+                ui.add_space(4.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Select, "SELECT");
+                ui.add_space(4.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Eyedropper, "PICK");
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Line, "LINE");
+                ui.add_space(4.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Rectangle, "RECT");
+                ui.add_space(4.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Ellipse, "ELLIPSE");
+                ui.add_space(4.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Box, "BOX");
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.selectable_value(&mut ui_state.tool, Tool::Text, "TEXT");
+            });
+
+            if matches!(ui_state.tool, Tool::Rectangle | Tool::Ellipse) {
+                ui.add_space(4.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.checkbox(&mut ui_state.shape_filled, "Filled");
+                });
+            }
+
+            if ui_state.tool == Tool::Fill {
+                ui.add_space(4.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.label("Match");
+                    ui.selectable_value(&mut ui_state.fill_match, FillMatch::Glyph, "GLYPH");
+                    ui.selectable_value(&mut ui_state.fill_match, FillMatch::Colors, "COLORS");
+                    ui.selectable_value(&mut ui_state.fill_match, FillMatch::All, "ALL");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.checkbox(&mut ui_state.fill_diagonal, "8-connectivity");
+                });
+            }
+
+            if ui_state.tool == Tool::Eyedropper {
+                ui.add_space(4.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.label("Pick");
+                    ui.selectable_value(&mut ui_state.pick, PickMode::Glyph, "GLYPH");
+                    ui.selectable_value(&mut ui_state.pick, PickMode::Colors, "COLORS");
+                    ui.selectable_value(&mut ui_state.pick, PickMode::All, "ALL");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.label("Alt+click picks with any tool");
+                });
+            }
+
+            if ui_state.tool == Tool::Select {
+                ui.add_space(4.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    let selected = selection.rect.is_some();
+                    if ui.add_enabled(selected, egui::Button::new("COPY")).clicked() { selection_events.send(SelectionEvent::Copy); }
+                    if ui.add_enabled(selected, egui::Button::new("CUT")).clicked() { selection_events.send(SelectionEvent::Cut); }
+                    if ui.add_enabled(selection.clipboard.is_some(), egui::Button::new("PASTE")).clicked() { selection_events.send(SelectionEvent::Paste); }
+                    if ui.add_enabled(selected, egui::Button::new("DELETE")).clicked() { selection_events.send(SelectionEvent::Delete); }
+                });
+
+                ui.add_space(4.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    if ui.add_enabled(selection.rect.is_some(), egui::Button::new("USE AS BRUSH")).clicked() {
+                        selection_events.send(SelectionEvent::Brush);
+                    }
+                });
+
+                ui.add_space(4.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    let floating = selection.floating.is_some();
+                    if ui.add_enabled(floating, egui::Button::new("ROTATE")).clicked() { selection_events.send(SelectionEvent::Rotate); }
+                    if ui.add_enabled(floating, egui::Button::new("FLIP")).clicked() { selection_events.send(SelectionEvent::Flip); }
+                });
+            }
+
+            if ui_state.tool == Tool::Box {
+                ui.add_space(4.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.selectable_value(&mut ui_state.box_style, BoxStyle::Single, "SINGLE");
+                    ui.selectable_value(&mut ui_state.box_style, BoxStyle::Double, "DOUBLE");
+                    ui.add_space(4.);
+                    ui.checkbox(&mut ui_state.box_rectangle, "Rectangle");
+                });
+
+                ui.add_space(4.);
+
+                egui::CollapsingHeader::new("Glyph mapping").id_source("box_glyphs").show(ui, |ui| {
+                    let glyphs = &mut files.box_glyphs;
+                    let table = match ui_state.box_style {
+                        BoxStyle::Single => &mut glyphs.single,
+                        BoxStyle::Double => &mut glyphs.double,
+                    };
+                    egui::Grid::new("box_glyphs_table").num_columns(4).spacing([8., 2.]).show(ui, |ui| {
+                        ui.label("");
+                        ui.label("#");
+                        ui.label("Rot.");
+                        ui.label("Flip");
+                        ui.end_row();
+                        for (label, id) in MASK_LABELS.iter().zip(table.iter_mut()) {
+                            ui.label(*label);
+                            ui.add(egui::DragValue::new(&mut id.index).clamp_range(0..=tiles.count() - 1));
+                            ui.add(egui::DragValue::new(&mut id.rotation).clamp_range(0..=3));
+                            ui.checkbox(&mut id.flip, "");
+                            ui.end_row();
+                        }
+                    });
+
+                    ui.add_space(4.);
+
+                    ui.horizontal(|ui| {
+                        if ui.button("SAVE").clicked() { files.box_glyphs_events.send(SaveBoxGlyphs); }
+                        ui.add_space(4.);
+                        ui.label(&glyphs.path);
+                    });
+                    if let Some(status) = &glyphs.status {
+                        ui.label(status);
+                    }
+                });
+            }
+
+            if ui_state.tool == Tool::Text {
+                ui.add_space(4.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(16.);
+                    ui.label("Click a cell and type, Esc to stop");
+                });
+
+                ui.add_space(4.);
+
+                egui::CollapsingHeader::new("Character mapping").id_source("char_map").show(ui, |ui| {
+                    let char_map = &mut files.char_map;
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut char_map.preset, CharPreset::Cp437, "CP437");
+                        ui.selectable_value(&mut char_map.preset, CharPreset::Ascii, "ASCII");
+                    });
+
+                    ui.add_space(4.);
+
+                    ui.horizontal(|ui| {
+                        ui.add(TextEdit::singleline(&mut ui_state.char_input).desired_width(24.));
+                        ui.add(egui::DragValue::new(&mut ui_state.char_glyph).clamp_range(0..=tiles.count() - 1));
+                        let c = ui_state.char_input.chars().next();
+                        if ui.add_enabled(c.is_some(), egui::Button::new("SET")).clicked() {
+                            if let Some(c) = c { char_map.chars.insert(c, ui_state.char_glyph); }
+                        }
+                    });
+
+                    let mut removed = None;
+                    for (c, index) in &char_map.chars {
+                        ui.horizontal(|ui| {
+                            ui.label(format!("{:?} #{}", c, index));
+                            if ui.small_button("x").clicked() { removed = Some(*c); }
+                        });
+                    }
+                    if let Some(c) = removed { char_map.chars.remove(&c); }
+
+                    ui.add_space(4.);
+
+                    ui.horizontal(|ui| {
+                        if ui.button("SAVE").clicked() { files.char_map_events.send(SaveCharMap); }
+                        ui.add_space(4.);
+                        ui.label(&char_map.path);
+                    });
+                    if let Some(status) = &char_map.status {
+                        ui.label(status);
+                    }
+                });
+            }
+
+            ui.add_space(16.);
+
+            if ui_state.tile.is_some() {
+                ui.horizontal(|ui| {
+                    ui.centered_and_justified(|ui| ui.heading("Selected tile"));
+                });
+
+                ui.add_space(8.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(24.);
+                    let y = ui_state.tile_id.index / tiles.columns;
+                    let x = ui_state.tile_id.index % tiles.columns;
+                    // In pixels of the tileset image, which may have a margin after the last full tile
+                    let [w, h] = ui_state.image.as_ref().unwrap().size().map(|s| s as f32);
+                    let tile = canvas.tile_size as f32;
+                    ui.add(ui_state.tile.unwrap().uv(egui::Rect::from_min_max(
+                        Pos2::new(x as f32 * tile / w, y as f32 * tile / h),
+                        Pos2::new((x + 1) as f32 * tile / w, (y + 1) as f32 * tile / h)
+                    )));
+                    ui.add_space(4.);
+                    if ui.button("ROTATE").clicked() { ui_state.tile_id.rotate(); }
+                    ui.add_space(4.);
+                    if ui.button("FLIP").clicked() { ui_state.tile_id.flip(); ui_state.tile_id.rotate(); ui_state.tile_id.rotate(); }
+                });
+
+                ui.add_space(8.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(24.);
+                    ui.checkbox(&mut ui_state.picker_open, "Tileset window");
+                });
+
+                ui.add_space(8.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(24.);
+                    ui.heading("#");
+                    let mut goto = ui_state.tile_id.index as f32;
+                    ui.add(egui::DragValue::new::<f32>(&mut goto).speed(1.0));
+                    ui_state.tile_id.index = goto as usize;
+
+                    ui.add_space(17.);
+
+                    let mut goto = ui_state.tile_id.index.to_string();
+                    ui.add(TextEdit::singleline(&mut goto).desired_width(49.));
+
+                    if let Ok(n) = goto.parse::<usize>() {
+                        ui_state.tile_id.index = n
+                    }
+                });
+
+                ui.add_space(8.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(24.);
+                    ui.heading("X");
+                    let mut goto_x = (ui_state.tile_id.index % tiles.columns) as f32;
+                    ui.add(egui::DragValue::new::<f32>(&mut goto_x).speed(0.2));
+
+                    ui.add_space(17.);
+
+                    ui.heading("Y");
+                    let mut goto_y = (ui_state.tile_id.index / tiles.columns) as f32;
+                    ui.add(egui::DragValue::new::<f32>(&mut goto_y).speed(0.2));
+
+                    let goto_x = (goto_x as usize).min(tiles.columns - 1);
+                    ui_state.tile_id.index = goto_x + goto_y as usize * tiles.columns;
+                });
+
+                ui.add_space(8.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(24.);
+                    if ui.button("- 1").clicked() { ui_state.tile_id.index = ui_state.tile_id.index.saturating_sub(1); }
+                    ui.add_space(2.);
+                    if ui.button("-10").clicked() { ui_state.tile_id.index = ui_state.tile_id.index.saturating_sub(10); }
+                    ui.add_space(2.);
+                    if ui.button("+ 1").clicked() { ui_state.tile_id.index += 1; }
+                    ui.add_space(2.);
+                    if ui.button("+10").clicked() { ui_state.tile_id.index += 10; }
+                });
+
+                ui_state.tile_id.index = ui_state.tile_id.index.min(tiles.count() - 1);
+
+                ui.add_space(8.);
+
+                ui.horizontal(|ui| {
+                    ui.add_space(24.);
+                    ui.label("Brush");
+                    ui.add(egui::DragValue::new(&mut ui_state.tileset_brush[0]).clamp_range(1..=16));
+                    ui.label("x");
+                    ui.add(egui::DragValue::new(&mut ui_state.tileset_brush[1]).clamp_range(1..=16));
+                    ui.add_space(4.);
+                    if ui.button("FROM TILESET").clicked() {
+                        let [w, h] = ui_state.tileset_brush;
+                        ui_state.brush = Some(Block::from_tileset(&tiles, ui_state.tile_id.index, w, h, ui_state.fg, ui_state.bg));
+                        ui_state.tool = Tool::Pencil;
+                    }
+                });
+
+                if let Some((w, h)) = ui_state.brush.as_ref().map(|b| (b.width, b.height)) {
+                    ui.add_space(4.);
+
+                    ui.horizontal(|ui| {
+                        ui.add_space(24.);
+                        ui.label(format!("Painting with a {}x{} brush", w, h));
+                        ui.add_space(4.);
+                        if ui.button("SINGLE TILE").clicked() { ui_state.brush = None; }
+                    });
+                }
+            }
+
+            ui.add_space(16.);
+
+            ui.horizontal(|ui| {
+                ui.centered_and_justified(|ui| ui.heading("View"));
+            });
+
+            ui.add_space(8.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.label(format!("Zoom {:.0}%", 100. / view.scale()));
+                ui.add_space(8.);
+                if ui.button("FIT").on_hover_text("Ctrl+0").clicked() { camera_events.send(CameraEvent::Fit); }
+                ui.add_space(4.);
+                if ui.button("100%").on_hover_text("Ctrl+1").clicked() { camera_events.send(CameraEvent::ActualSize); }
+            });
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.checkbox(&mut ui_state.layers_open, "Layers window");
+            });
+
+            ui.add_space(4.);
+
+            // Only write the overlay back when edited, it's redrawn on changes
+            let (mut grid, mut major, mut show_guides) = (overlay.grid, overlay.major, overlay.show_guides);
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                ui.checkbox(&mut grid, "Grid").on_hover_text("G");
+                ui.label("every");
+                ui.add(egui::DragValue::new(&mut major).clamp_range(0..=64));
+                ui.add_space(4.);
+                ui.checkbox(&mut show_guides, "Guides").on_hover_text("H");
+            });
+            if (grid, major, show_guides) != (overlay.grid, overlay.major, overlay.show_guides) {
+                overlay.grid = grid;
+                overlay.major = major;
+                overlay.show_guides = show_guides;
+            }
+
+            ui.add_space(4.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                let guide = &mut ui_state.new_guide;
+                ui.selectable_value(&mut guide.vertical, true, "V");
+                ui.selectable_value(&mut guide.vertical, false, "H");
+                let limit = if guide.vertical { canvas.width } else { canvas.height };
+                ui.add(egui::DragValue::new(&mut guide.pos).clamp_range(0..=limit));
+                ui.add_space(4.);
+                if ui.button("ADD GUIDE").on_hover_text("Shift+V / Shift+H on the canvas").clicked() && !overlay.guides.contains(guide) {
+                    overlay.guides.push(*guide);
+                }
+            });
+
+            let mut removed = None;
+            for (i, guide) in overlay.guides.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.add_space(24.);
+                    ui.label(format!("{} {}", if guide.vertical { "Vertical" } else { "Horizontal" }, guide.pos));
+                    if ui.small_button("x").clicked() { removed = Some(i); }
+                });
+            }
+            if let Some(i) = removed { overlay.guides.remove(i); }
+
+            ui.add_space(16.);
+
+            ui.horizontal(|ui| {
+                ui.centered_and_justified(|ui| ui.heading("History"));
+            });
+
+            ui.add_space(8.);
+
+            ui.horizontal(|ui| {
+                ui.add_space(16.);
+                if ui.button("UNDO").clicked() { history_events.send(HistoryEvent::Undo); }
+                ui.add_space(4.);
+                if ui.button("REDO").clicked() { history_events.send(HistoryEvent::Redo); }
+                ui.add_space(8.);
+                ui.label("Depth");
+                let mut limit = history.limit as f32;
+                ui.add(egui::DragValue::new::<f32>(&mut limit).speed(1.0).clamp_range(1.0..=10000.0));
+                if limit as usize != history.limit {
+                    history.limit = limit as usize;
+                    history.trim();
+                }
+            });
+
+            ui.add_space(4.);
+
+            egui::ScrollArea::vertical().id_source("history").max_height(120.).show(ui, |ui| {
+                let position = history.position();
+                if ui.selectable_label(position == 0, "Initial").clicked() {
+                    history_events.send(HistoryEvent::Jump(0));
+                }
+                for (i, step) in history.steps().iter().enumerate() {
+                    if ui.selectable_label(position == i + 1, &step.name).clicked() {
+                        history_events.send(HistoryEvent::Jump(i + 1));
+                    }
+                }
+            });
+        });
+
+    let mut open = ui_state.new_canvas.open;
+    egui::Window::new("New canvas")
+        .open(&mut open)
+        .resizable(false)
+        .collapsible(false)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            let dialog = &mut ui_state.new_canvas;
+            egui::Grid::new("new_canvas").num_columns(2).spacing([8., 4.]).show(ui, |ui| {
+                ui.label("Tileset");
+                ui.add(TextEdit::singleline(&mut dialog.tileset).desired_width(160.));
+                ui.end_row();
+                ui.label("Tile size");
+                ui.add(egui::DragValue::new(&mut dialog.tile_size).clamp_range(1..=64));
+                ui.end_row();
+                ui.label("Width");
+                ui.add(egui::DragValue::new(&mut dialog.width).clamp_range(1..=1024));
+                ui.end_row();
+                ui.label("Height");
+                ui.add(egui::DragValue::new(&mut dialog.height).clamp_range(1..=1024));
+                ui.end_row();
+            });
+
+            ui.add_space(8.);
+
+            if ui.button("CREATE").clicked() {
+                new_canvas.send(NewCanvas {
+                    tileset: dialog.tileset.clone(),
+                    tile_size: dialog.tile_size,
+                    width: dialog.width,
+                    height: dialog.height,
+                    layers: None,
+                    palette: None,
+                    path: None,
+                    status: format!("New {}×{} canvas", dialog.width, dialog.height),
+                });
+                dialog.open = false;
+            }
+        });
+    ui_state.new_canvas.open &= open;
+
+    let mut open = ui_state.picker_open;
+    egui::Window::new("Tileset")
+        .open(&mut open)
+        .default_pos([240., 16.])
+        .default_size([320., 320.])
+        .show(egui_ctx.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Zoom");
+                ui.add(egui::Slider::new(&mut ui_state.picker_zoom, 1.0..=6.0).step_by(0.5));
+            });
+
+            ui.add_space(4.);
+
+            let (texture, size) = match &ui_state.image {
+                Some(image) => (image.texture_id(ui.ctx()), image.size_vec2()),
+                None => {
+                    ui.label(format!("Couldn't load {}", canvas.tileset));
+                    return;
+                }
+            };
+            let tile = canvas.tile_size as f32 * ui_state.picker_zoom;
+
+            egui::ScrollArea::both().id_source("tileset_picker").show(ui, |ui| {
+                let response = ui.add(egui::Image::new(texture, size * ui_state.picker_zoom).sense(egui::Sense::click()));
+                let origin = response.rect.min;
+                let glyph_rect = |index: usize| egui::Rect::from_min_size(
+                    origin + egui::vec2((index % tiles.columns) as f32, (index / tiles.columns) as f32) * tile,
+                    egui::vec2(tile, tile),
+                );
+                // Pixels after the last full tile aren't glyphs
+                let hovered = response.hover_pos()
+                    .map(|pos| (((pos.x - origin.x) / tile) as usize, ((pos.y - origin.y) / tile) as usize))
+                    .filter(|&(x, y)| x < tiles.columns && y < tiles.rows)
+                    .map(|(x, y)| x + y * tiles.columns);
+
+                ui.painter().rect_stroke(glyph_rect(ui_state.tile_id.index), 0., (2., Color32::YELLOW));
+                if let Some(index) = hovered {
+                    ui.painter().rect_stroke(glyph_rect(index), 0., (1., Color32::WHITE));
+                    if response.clicked() { ui_state.tile_id.index = index; }
+                    response.on_hover_text(format!("#{}", index));
+                }
+            });
+        });
+    ui_state.picker_open = open;
+}
+
+/// Options of the image converter, which replaces the canvas.
+fn import_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    mut settings: ResMut<ImportSettings>,
+    mut events: EventWriter<ImportEvent>,
+) {
+    let mut open = settings.open;
+    egui::Window::new("Import image")
+        .open(&mut open)
+        .resizable(false)
+        .collapsible(false)
+        .show(egui_ctx.ctx_mut(), |ui| {
+            egui::Grid::new("import_image").num_columns(2).spacing([8., 4.]).show(ui, |ui| {
+                ui.label("Image");
+                ui.add(TextEdit::singleline(&mut settings.path).desired_width(160.).hint_text("image.png"));
+                ui.end_row();
+                ui.label("Glyphs");
+                ui.add_enabled(!settings.color_only, TextEdit::singleline(&mut settings.glyphs).desired_width(160.).hint_text("all, or 0-127,176-178"));
+                ui.end_row();
+            });
+
+            ui.add_space(4.);
+
+            ui.checkbox(&mut settings.color_only, "Colors only");
+            ui.add_enabled(!settings.color_only, egui::Checkbox::new(&mut settings.variants, "Flips and rotations"));
+            ui.checkbox(&mut settings.dither, "Dithering");
+
+            ui.add_space(8.);
+
+            ui.horizontal(|ui| {
+                let import = ui.add_enabled(!settings.converting, egui::Button::new("IMPORT"));
+                if import.on_hover_text("Uses the tileset and palette of the canvas").clicked() { events.send(ImportEvent); }
+                if let Some(status) = &settings.status { ui.label(status); }
+            });
+        });
+    settings.open &= open;
+}
+
+/// Layer list, top layer first, and the settings of the active layer.
+fn layers_window(
+    mut egui_ctx: ResMut<EguiContext>,
+    grid: Res<Grid>,
+    mut ui_state: ResMut<UiState>,
+    mut events: EventWriter<LayerEvent>,
+) {
+    let mut open = ui_state.layers_open;
+    egui::Window::new("Layers")
+        .open(&mut open)
+        .resizable(false)
+        .default_pos([240., 400.])
+        .show(egui_ctx.ctx_mut(), |ui| {
+            for (i, layer) in grid.layers().iter().enumerate().rev() {
+                ui.horizontal(|ui| {
+                    let mut visible = layer.visible;
+                    if ui.checkbox(&mut visible, "").on_hover_text("Visible").changed() {
+                        events.send(LayerEvent::SetVisible(i, visible));
+                    }
+                    let mut locked = layer.locked;
+                    if ui.checkbox(&mut locked, "").on_hover_text("Locked").changed() {
+                        events.send(LayerEvent::SetLocked(i, locked));
+                    }
+                    if ui.selectable_label(i == grid.active, &layer.name).clicked() {
+                        events.send(LayerEvent::Select(i));
+                    }
+                });
+            }
+
+            ui.add_space(8.);
+
+            let active = grid.active_layer();
+            let mut name = active.name.clone();
+            let mut opacity = active.opacity;
+            egui::Grid::new("layer_settings").num_columns(2).spacing([8., 4.]).show(ui, |ui| {
+                ui.label("Name");
+                if ui.add(TextEdit::singleline(&mut name).desired_width(120.)).changed() {
+                    events.send(LayerEvent::Rename(grid.active, name));
+                }
+                ui.end_row();
+                ui.label("Opacity");
+                if ui.add(egui::Slider::new(&mut opacity, 0.0..=1.0)).changed() {
+                    events.send(LayerEvent::SetOpacity(grid.active, opacity));
+                }
+                ui.end_row();
+            });
+
+            ui.add_space(8.);
+
+            let (top, count) = (grid.active + 1 == grid.layers().len(), grid.layers().len());
+            ui.horizontal(|ui| {
+                if ui.button("ADD").clicked() { events.send(LayerEvent::Add); }
+                if ui.add_enabled(count > 1, egui::Button::new("REMOVE")).clicked() { events.send(LayerEvent::Remove); }
+                if ui.add_enabled(!top, egui::Button::new("UP")).clicked() { events.send(LayerEvent::Raise); }
+                if ui.add_enabled(grid.active > 0, egui::Button::new("DOWN")).clicked() { events.send(LayerEvent::Lower); }
+                if ui.add_enabled(grid.active > 0, egui::Button::new("MERGE")).on_hover_text("Merge down").clicked() {
+                    events.send(LayerEvent::MergeDown);
+                }
+            });
+        });
+    ui_state.layers_open = open;
+}
\ No newline at end of file
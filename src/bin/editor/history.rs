@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use crate::Grid;
+use crate::layers::{LayerStack, toggle_layer};
+use crate::paint::CellWriter;
+use bevy_textmode::{Cell, TilePos};
+
+pub struct HistoryPlugin;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<History>()
+            .add_event::<HistoryEvent>()
+            .add_system(history_shortcuts)
+            .add_system(handle_history_events.after(history_shortcuts));
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Change {
+    /// Id of the edited layer.
+    pub layer: u32,
+    pub pos: TilePos,
+    pub before: Cell,
+    pub after: Cell,
+}
+
+/// Layer added or deleted by a step. Deleted layers are kept hidden in the grid while the step
+/// is in the history, and a layer moved in the stack is deleted and added back.
+#[derive(Copy, Clone)]
+pub struct LayerChange {
+    pub layer: u32,
+    /// Index of the layer in the stack while it exists.
+    pub index: usize,
+    /// Whether the step adds the layer, otherwise it deletes it.
+    pub added: bool,
+}
+
+/// A group of changes undone and redone together.
+///
+/// Cell changes are applied before layer changes, and undone after them.
+pub struct Step {
+    pub name: String,
+    pub changes: Vec<Change>,
+    pub layers: Vec<LayerChange>,
+}
+
+/// Undo/redo stack of canvas edits.
+///
+/// `steps[..position]` are applied, `steps[position..]` can be redone.
+pub struct History {
+    steps: Vec<Step>,
+    position: usize,
+    /// Maximum number of steps kept.
+    pub limit: usize,
+    pending: Vec<Change>,
+    pending_layers: Vec<LayerChange>,
+    pending_name: Option<&'static str>,
+    /// Layers of the steps dropped from the history, removed layers among them can be despawned.
+    dropped: Vec<u32>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            steps: vec![],
+            position: 0,
+            limit: 100,
+            pending: vec![],
+            pending_layers: vec![],
+            pending_name: None,
+            dropped: vec![],
+        }
+    }
+}
+
+impl History {
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Adds a cell change to the current step.
+    pub fn record(&mut self, layer: u32, pos: TilePos, before: Cell, after: Cell) {
+        if before != after {
+            self.pending.push(Change { layer, pos, before, after });
+        }
+    }
+
+    /// Adds a layer change to the current step, the layer having been added or deleted already.
+    pub fn record_layer(&mut self, layer: u32, index: usize, added: bool) {
+        self.pending_layers.push(LayerChange { layer, index, added });
+    }
+
+    /// Sets the name of the current step, "Paint" by default.
+    pub fn name(&mut self, name: &'static str) {
+        self.pending_name = Some(name);
+    }
+
+    /// Closes the current step, discarding steps that could be redone.
+    pub fn commit(&mut self) {
+        let name = self.pending_name.take().unwrap_or("Paint");
+        if self.pending.is_empty() && self.pending_layers.is_empty() { return; }
+
+        // Only keep the first `before` and last `after` of each cell
+        let mut merged: Vec<Change> = vec![];
+        let mut indices: HashMap<(u32, u32, u32), usize> = HashMap::new();
+        for change in self.pending.drain(..) {
+            let key = (change.layer, change.pos.x, change.pos.y);
+            match indices.get(&key) {
+                Some(&i) => merged[i].after = change.after,
+                None => {
+                    indices.insert(key, merged.len());
+                    merged.push(change);
+                }
+            }
+        }
+        merged.retain(|c| c.before != c.after);
+        let layers = std::mem::take(&mut self.pending_layers);
+        if merged.is_empty() && layers.is_empty() { return; }
+
+        let name = if merged.is_empty() { name.to_string() } else { format!("{} ({})", name, merged.len()) };
+        let redone = self.steps.split_off(self.position.min(self.steps.len()));
+        self.drop_steps(redone, false);
+        self.steps.push(Step { name, changes: merged, layers });
+        self.position = self.steps.len();
+        self.trim();
+    }
+
+    /// Drops the steps above `limit`, those that could be redone first, then the oldest ones.
+    pub fn trim(&mut self) {
+        let redone = self.steps.split_off(self.position.max(self.limit).min(self.steps.len()));
+        self.drop_steps(redone, false);
+        if self.steps.len() > self.limit {
+            let excess = self.steps.len() - self.limit;
+            let oldest = self.steps.drain(..excess).collect();
+            self.drop_steps(oldest, true);
+            self.position -= excess;
+        }
+    }
+
+    /// Queues the layers that the dropped `steps` leave deleted: those deleted by the end of an
+    /// applied step, or added by an undone one.
+    fn drop_steps(&mut self, steps: Vec<Step>, applied: bool) {
+        for step in steps {
+            let mut changes = step.layers;
+            if applied { changes.reverse(); }
+            let mut seen = vec![];
+            for change in changes {
+                if seen.contains(&change.layer) { continue; }
+                seen.push(change.layer);
+                if change.added != applied { self.dropped.push(change.layer); }
+            }
+        }
+    }
+
+    /// Layers of the steps dropped since the last call.
+    pub fn take_dropped(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.dropped)
+    }
+
+    pub fn has_dropped(&self) -> bool {
+        !self.dropped.is_empty()
+    }
+
+    /// Changes the palette indices of the recorded cells, see [`Cell::remap`].
+    pub fn remap(&mut self, f: &dyn Fn(usize) -> usize) {
+        let changes = self.steps.iter_mut().flat_map(|step| step.changes.iter_mut()).chain(self.pending.iter_mut());
+        for change in changes {
+            change.before.remap(f);
+            change.after.remap(f);
+        }
+    }
+
+    /// Forgets every step, the removed layers of the grid being despawned by the caller.
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.pending.clear();
+        self.pending_layers.clear();
+        self.pending_name = None;
+        self.dropped.clear();
+        self.position = 0;
+    }
+}
+
+pub enum HistoryEvent {
+    Undo,
+    Redo,
+    /// Go to the state after the first `n` steps.
+    Jump(usize),
+}
+
+fn history_shortcuts(
+    mut egui_ctx: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    mut events: EventWriter<HistoryEvent>,
+) {
+    if egui_ctx.ctx_mut().wants_keyboard_input() { return; }
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if ctrl && keys.just_pressed(KeyCode::Z) {
+        events.send(if shift { HistoryEvent::Redo } else { HistoryEvent::Undo });
+    } else if ctrl && keys.just_pressed(KeyCode::Y) {
+        events.send(HistoryEvent::Redo);
+    }
+}
+
+fn handle_history_events(
+    mut events: EventReader<HistoryEvent>,
+    mut history: ResMut<History>,
+    // Layer changes move layers in and out of the grid read by the writer
+    mut params: ParamSet<(CellWriter, ResMut<Grid>)>,
+    mut stack: LayerStack,
+) {
+    for event in events.iter() {
+        history.commit();
+        let target = match event {
+            HistoryEvent::Undo => history.position.saturating_sub(1),
+            HistoryEvent::Redo => (history.position + 1).min(history.steps.len()),
+            HistoryEvent::Jump(n) => (*n).min(history.steps.len()),
+        };
+
+        while history.position > target {
+            history.position -= 1;
+            let step = &history.steps[history.position];
+            for change in step.layers.iter().rev() {
+                toggle_layer(&mut params.p1(), &mut stack, change.layer, change.index, !change.added);
+            }
+            let mut writer = params.p0();
+            step.changes.iter().rev().for_each(|c| { writer.set_layer(c.layer, &c.pos, &c.before); });
+        }
+        while history.position < target {
+            let step = &history.steps[history.position];
+            let mut writer = params.p0();
+            step.changes.iter().for_each(|c| { writer.set_layer(c.layer, &c.pos, &c.after); });
+            for change in &step.layers {
+                toggle_layer(&mut params.p1(), &mut stack, change.layer, change.index, change.added);
+            }
+            history.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy_textmode::TileId;
+    use super::*;
+
+    fn cell(fg: usize) -> Cell {
+        Cell { id: TileId::new(), fg, bg: 0 }
+    }
+
+    fn paint(history: &mut History, x: u32, before: usize, after: usize) {
+        history.record(0, TilePos { x, y: 0 }, cell(before), cell(after));
+    }
+
+    #[test]
+    fn commit_merges_changes_of_a_cell() {
+        let mut history = History::default();
+        paint(&mut history, 0, 0, 1);
+        paint(&mut history, 0, 1, 2);
+        paint(&mut history, 1, 0, 1);
+        paint(&mut history, 1, 1, 0);
+        history.name("Line");
+        history.commit();
+
+        assert_eq!(history.position(), 1);
+        let step = &history.steps()[0];
+        assert_eq!(step.name, "Line (1)");
+        assert_eq!(step.changes.len(), 1);
+        assert!(step.changes[0].before == cell(0) && step.changes[0].after == cell(2));
+    }
+
+    #[test]
+    fn empty_steps_are_skipped() {
+        let mut history = History::default();
+        history.commit();
+        paint(&mut history, 0, 3, 3);
+        paint(&mut history, 1, 0, 1);
+        paint(&mut history, 1, 1, 0);
+        history.commit();
+        assert!(history.steps().is_empty());
+
+        history.record_layer(1, 1, true);
+        history.name("Add layer");
+        history.commit();
+        assert_eq!(history.steps()[0].name, "Add layer");
+    }
+
+    #[test]
+    fn commit_discards_redo_steps() {
+        let mut history = History::default();
+        history.record_layer(1, 1, true);
+        history.commit();
+        paint(&mut history, 0, 0, 1);
+        history.commit();
+        history.position = 0;
+
+        paint(&mut history, 0, 0, 2);
+        history.commit();
+        assert_eq!(history.steps().len(), 1);
+        assert_eq!(history.position(), 1);
+        assert_eq!(history.take_dropped(), vec![1]);
+        assert!(!history.has_dropped());
+    }
+
+    #[test]
+    fn moved_layers_are_not_dropped() {
+        let mut history = History { limit: 1, ..History::default() };
+        for _ in 0..2 {
+            history.record_layer(1, 0, false);
+            history.record_layer(1, 1, true);
+            history.commit();
+        }
+        assert!(!history.has_dropped());
+
+        history.position = 0;
+        paint(&mut history, 0, 0, 1);
+        history.commit();
+        assert!(!history.has_dropped());
+
+        history.record_layer(2, 1, false);
+        history.commit();
+        paint(&mut history, 0, 1, 2);
+        history.commit();
+        assert_eq!(history.take_dropped(), vec![2]);
+    }
+
+    #[test]
+    fn trim_keeps_the_newest_steps() {
+        let mut history = History { limit: 3, ..History::default() };
+        for i in 0..5 {
+            paint(&mut history, i, 0, 1);
+            history.commit();
+        }
+        assert_eq!(history.steps().len(), 3);
+        assert_eq!(history.position(), 3);
+        assert_eq!(history.steps()[0].changes[0].pos.x, 2);
+
+        // Steps that could be redone go first
+        history.position = 1;
+        history.limit = 2;
+        history.trim();
+        assert_eq!(history.steps().len(), 2);
+        assert_eq!(history.position(), 1);
+        assert_eq!(history.steps()[1].changes[0].pos.x, 3);
+
+        history.limit = 1;
+        history.trim();
+        assert_eq!(history.steps().len(), 1);
+        assert_eq!(history.position(), 1);
+        assert_eq!(history.steps()[0].changes[0].pos.x, 2);
+
+        history.limit = 0;
+        history.trim();
+        assert!(history.steps().is_empty());
+        assert_eq!(history.position(), 0);
+    }
+
+    #[test]
+    fn clear_forgets_everything() {
+        let mut history = History::default();
+        history.record_layer(1, 1, false);
+        history.commit();
+        paint(&mut history, 0, 0, 1);
+        history.clear();
+        history.commit();
+        assert!(history.steps().is_empty());
+        assert_eq!(history.position(), 0);
+        assert!(!history.has_dropped());
+    }
+}
\ No newline at end of file
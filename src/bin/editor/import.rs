@@ -0,0 +1,323 @@
+use std::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use futures_lite::future;
+use bevy_textmode::{Canvas, Cell, Colors, TileId, Tiles};
+use crate::NewCanvas;
+use crate::layers::LayerData;
+
+pub struct ImportPlugin;
+
+impl Plugin for ImportPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(ImportSettings {
+                open: false,
+                path: "image.png".to_string(),
+                glyphs: String::new(),
+                variants: true,
+                dither: false,
+                color_only: false,
+                status: None,
+                converting: false,
+            })
+            .insert_resource(ImportTask(None))
+            .add_event::<ImportEvent>()
+            .add_system(import)
+            .add_system(finish_import.after(import));
+    }
+}
+
+/// Settings of the "Import image" window.
+#[derive(Clone)]
+pub struct ImportSettings {
+    pub open: bool,
+    pub path: String,
+    /// Glyphs the converter may use, like `0-127,176-178`. Every glyph if empty.
+    pub glyphs: String,
+    /// Also try the flipped and rotated glyphs.
+    pub variants: bool,
+    /// Diffuses the error of each cell to the cells not converted yet.
+    pub dither: bool,
+    /// Only picks a color per cell, every cell is a flat block.
+    pub color_only: bool,
+    pub status: Option<String>,
+    /// Whether a conversion is running in the background.
+    pub converting: bool,
+}
+
+/// Conversion running on the [`AsyncComputeTaskPool`].
+struct ImportTask(Option<Task<Conversion>>);
+
+/// Result of a conversion with the tileset and palette it was made for.
+struct Conversion {
+    path: String,
+    tileset: String,
+    tile_size: u32,
+    colors: Colors,
+    palette: String,
+    result: Result<(u32, u32, Vec<Cell>), String>,
+}
+
+/// Converts the image at `ImportSettings.path` to a new canvas using the current tileset and palette.
+pub struct ImportEvent;
+
+/// Starts converting the image on the async compute pool, large images take seconds.
+fn import(
+    mut events: EventReader<ImportEvent>,
+    mut settings: ResMut<ImportSettings>,
+    mut task: ResMut<ImportTask>,
+    pool: Res<AsyncComputeTaskPool>,
+    canvas: Res<Canvas>,
+    tiles: Res<Tiles>,
+    colors: Res<Colors>,
+) {
+    for _ in events.iter() {
+        let (options, tiles, colors) = (settings.clone(), tiles.clone(), colors.clone());
+        let (tileset, tile_size, palette) = (canvas.tileset.clone(), canvas.tile_size, canvas.palette.clone());
+        settings.status = Some(format!("Converting {}…", settings.path));
+        settings.converting = true;
+        // Dropping the previous task cancels it
+        task.0 = Some(pool.spawn(async move {
+            let result = image::open(&options.path)
+                .map_err(|e| format!("Couldn't open {}: {}", options.path, e))
+                .and_then(|img| convert(&img.to_rgba8(), &tiles, &colors, tile_size, &options));
+            Conversion { path: options.path, tileset, tile_size, colors, palette, result }
+        }));
+    }
+}
+
+/// Opens the converted image once its task is done. The document path is only reset by
+/// `new_canvas` if the canvas is accepted.
+fn finish_import(
+    mut settings: ResMut<ImportSettings>,
+    mut task: ResMut<ImportTask>,
+    mut new_canvas: EventWriter<NewCanvas>,
+) {
+    let conversion = match &mut task.0 {
+        Some(task) => match future::block_on(future::poll_once(task)) {
+            Some(conversion) => conversion,
+            None => return,
+        },
+        None => return,
+    };
+    task.0 = None;
+    settings.converting = false;
+    settings.status = Some(match conversion.result {
+        Ok((width, height, cells)) => {
+            new_canvas.send(NewCanvas {
+                tileset: conversion.tileset,
+                tile_size: conversion.tile_size,
+                width,
+                height,
+                layers: Some(vec![LayerData::new("Image", cells)]),
+                palette: Some((conversion.colors, conversion.palette)),
+                path: None,
+                status: format!("Imported {}", conversion.path),
+            });
+            format!("Converted {} ({}×{} cells)", conversion.path, width, height)
+        }
+        Err(error) => error,
+    });
+}
+
+/// Glyph indices listed in `spec`, comma separated indices or ranges like `176-178`.
+pub fn parse_glyphs(spec: &str, count: usize) -> Result<Vec<usize>, String> {
+    if spec.trim().is_empty() { return Ok((0..count).collect()); }
+    let number = |s: &str| s.trim().parse::<usize>().map_err(|_| format!("Invalid glyph {}", s.trim()));
+    let mut glyphs = vec![];
+    for part in spec.split(',').filter(|part| !part.trim().is_empty()) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (number(start)?, number(end)?),
+            None => (number(part)?, number(part)?),
+        };
+        for glyph in start..=end.min(count.saturating_sub(1)) {
+            if !glyphs.contains(&glyph) { glyphs.push(glyph); }
+        }
+    }
+    if glyphs.is_empty() { return Err("No glyph of the tileset selected".to_string()); }
+    Ok(glyphs)
+}
+
+/// Tile of the converter with the pixels it lights, in the cell's reading order.
+struct Shape {
+    id: TileId,
+    lit: Vec<usize>,
+}
+
+/// Glyphs to try, skipping variants that light the same pixels as an earlier one.
+fn shapes(tiles: &Tiles, glyphs: &[usize], variants: bool, size: u32) -> Vec<Shape> {
+    let mut seen = HashSet::new();
+    let mut shapes = vec![];
+    for &index in glyphs {
+        for variant in 0..if variants { 8 } else { 1 } {
+            let id = TileId { index, flip: variant >= 4, rotation: variant % 4 };
+            let lit = (0..size * size)
+                .filter(|i| tiles.lit(&id, i % size, i / size))
+                .map(|i| i as usize)
+                .collect::<Vec<usize>>();
+            if seen.insert(lit.clone()) { shapes.push(Shape { id, lit }); }
+        }
+    }
+    shapes
+}
+
+/// Oklab coordinates of `color`, where euclidean distances follow perceived differences.
+fn oklab(color: Color) -> Vec3 {
+    let [r, g, b, _] = color.as_linear_rgba_f32();
+    let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+    Vec3::new(
+        0.21045426 * l + 0.7936178 * m - 0.00407205 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.02590404 * l + 0.78277177 * m - 0.80867577 * s,
+    )
+}
+
+/// Palette entries nearest to colors, cached by rounded color since cells often share averages.
+struct Nearest {
+    palette: Vec<Vec3>,
+    cache: HashMap<[i32; 3], usize>,
+}
+
+impl Nearest {
+    fn get(&mut self, color: Vec3) -> usize {
+        let key = (color * 256.).round().to_array().map(|c| c as i32);
+        let palette = &self.palette;
+        *self.cache.entry(key).or_insert_with(|| palette.iter().enumerate()
+            .min_by(|(_, a), (_, b)| a.distance_squared(color).partial_cmp(&b.distance_squared(color)).unwrap())
+            .map(|(i, _)| i)
+            .unwrap_or(0))
+    }
+}
+
+/// Splits `img` into cells and picks the tile and colors of each that are closest to its pixels.
+/// Pixels after the last full cell are cropped and transparent ones are drawn over black.
+///
+/// Returns the size of the canvas in cells and its content, bottom row first.
+pub fn convert(
+    img: &image::RgbaImage,
+    tiles: &Tiles,
+    colors: &Colors,
+    size: u32,
+    settings: &ImportSettings,
+) -> Result<(u32, u32, Vec<Cell>), String> {
+    let (width, height) = (img.width() / size, img.height() / size);
+    if width == 0 || height == 0 { return Err(format!("The image is smaller than a {}px tile", size)); }
+
+    let shapes = if settings.color_only {
+        vec![]
+    } else {
+        shapes(tiles, &parse_glyphs(&settings.glyphs, tiles.count())?, settings.variants, size)
+    };
+    let palette = colors.iter().map(|&c| oklab(c)).collect::<Vec<Vec3>>();
+    let mut nearest = Nearest { palette: palette.clone(), cache: HashMap::new() };
+
+    let pixel_width = (width * size) as usize;
+    let mut pixels = (0..height * size)
+        .flat_map(|y| (0..width * size).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let [r, g, b, a] = img.get_pixel(x, y).0;
+            let [r, g, b, _] = Color::rgb_u8(r, g, b).as_linear_rgba_f32().map(|c| c * a as f32 / 255.);
+            oklab(Color::rgb_linear(r, g, b))
+        })
+        .collect::<Vec<Vec3>>();
+
+    let mut cells = vec![Cell::empty(); (width * height) as usize];
+    let mut block = vec![Vec3::ZERO; (size * size) as usize];
+    for cy in 0..height {
+        for cx in 0..width {
+            let origin = (cx * size) as usize + (cy * size) as usize * pixel_width;
+            for (i, pixel) in block.iter_mut().enumerate() {
+                *pixel = pixels[origin + i % size as usize + i / size as usize * pixel_width];
+            }
+            let total: Vec3 = block.iter().sum();
+
+            // The best colors for a shape are the entries nearest to the mean of its lit and unlit
+            // pixels, the error then only depends on the distance to those means
+            let mut cell = {
+                let color = nearest.get(total / block.len() as f32);
+                Cell { id: TileId::new(), fg: color, bg: color }
+            };
+            let mut best = f32::MAX;
+            for shape in &shapes {
+                let lit: Vec3 = shape.lit.iter().map(|&i| &block[i]).sum();
+                let (lit_count, unlit_count) = (shape.lit.len() as f32, (block.len() - shape.lit.len()) as f32);
+                let mut error = 0.;
+                let fg = if lit_count > 0. {
+                    let mean = lit / lit_count;
+                    let fg = nearest.get(mean);
+                    error += lit_count * palette[fg].distance_squared(mean);
+                    Some(fg)
+                } else {
+                    None
+                };
+                let bg = if unlit_count > 0. {
+                    let mean = (total - lit) / unlit_count;
+                    let bg = nearest.get(mean);
+                    error += unlit_count * palette[bg].distance_squared(mean);
+                    Some(bg)
+                } else {
+                    None
+                };
+                if error < best {
+                    best = error;
+                    let (fg, bg) = (fg.or(bg).unwrap_or(0), bg.or(fg).unwrap_or(0));
+                    cell = Cell { id: shape.id, fg, bg };
+                }
+            }
+
+            if settings.dither {
+                // Floyd-Steinberg on the pixels of the cells converted after this one
+                let (x0, y0) = (cx * size, cy * size);
+                let pending = |x: u32, y: u32| x < width * size && y < height * size
+                    && (y / size > cy || (y / size == cy && x / size > cx));
+                for (i, &pixel) in block.iter().enumerate() {
+                    let (x, y) = (x0 + i as u32 % size, y0 + i as u32 / size);
+                    let lit = tiles.lit(&cell.id, x - x0, y - y0);
+                    let error = pixel - palette[if lit { cell.fg } else { cell.bg }];
+                    for (dx, dy, weight) in [(1, 0, 7.), (-1, 1, 3.), (0, 1, 5.), (1, 1, 1.)] {
+                        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                        if nx < 0 || !pending(nx as u32, ny as u32) { continue; }
+                        pixels[nx as usize + ny as usize * pixel_width] += error * weight / 16.;
+                    }
+                }
+            }
+
+            // The grid's y axis points up, the image's points down
+            cells[(cx + (height - 1 - cy) * width) as usize] = cell;
+        }
+    }
+
+    Ok((width, height, cells))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyph_lists() {
+        assert_eq!(parse_glyphs("", 4), Ok(vec![0, 1, 2, 3]));
+        assert_eq!(parse_glyphs(" 3, 1-2 ,2", 8), Ok(vec![3, 1, 2]));
+        // Ranges are clipped to the tileset
+        assert_eq!(parse_glyphs("6-300", 8), Ok(vec![6, 7]));
+    }
+
+    #[test]
+    fn glyph_list_errors() {
+        assert_eq!(parse_glyphs("1,a", 8), Err("Invalid glyph a".to_string()));
+        assert_eq!(parse_glyphs("2-", 8), Err("Invalid glyph ".to_string()));
+        assert_eq!(parse_glyphs("9-12", 8), Err("No glyph of the tileset selected".to_string()));
+        assert_eq!(parse_glyphs("3-1", 8), Err("No glyph of the tileset selected".to_string()));
+        assert_eq!(parse_glyphs(",", 8), Err("No glyph of the tileset selected".to_string()));
+    }
+
+    #[test]
+    fn oklab_orders_grays() {
+        let (black, gray, white) = (oklab(Color::BLACK), oklab(Color::GRAY), oklab(Color::WHITE));
+        assert!(black.x < gray.x && gray.x < white.x);
+        assert!((white.x - 1.).abs() < 1e-3 && white.y.abs() < 1e-3 && white.z.abs() < 1e-3);
+    }
+}
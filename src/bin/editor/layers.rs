@@ -0,0 +1,264 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use bevy_textmode::{BasicMesh, Canvas, CanvasMaterial, Cell, TileColors, TileId, TileMaterial, TilePos, Tiles, TRANSPARENT};
+use crate::{CellSpawner, Grid, Layer};
+use crate::history::History;
+use crate::paint::{apply_paint, PaintEvent};
+
+/// Depth between two layers, previews and overlays are drawn above `1.0`.
+pub const LAYER_Z_STEP: f32 = 0.01;
+
+pub struct LayersPlugin;
+
+impl Plugin for LayersPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<LayerEvent>()
+            .add_system(handle_layer_events.before(apply_paint))
+            .add_system(despawn_dropped_layers);
+    }
+}
+
+/// Content and settings of a layer, as saved in documents.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LayerData {
+    pub name: String,
+    pub visible: bool,
+    pub locked: bool,
+    pub opacity: f32,
+    /// Cells indexed by `x + y * width`.
+    pub cells: Vec<Cell>,
+}
+
+impl LayerData {
+    pub fn new(name: &str, cells: Vec<Cell>) -> Self {
+        LayerData { name: name.to_string(), visible: true, locked: false, opacity: 1., cells }
+    }
+}
+
+/// Layer actions triggered from the GUI, indices count from the bottom layer.
+pub enum LayerEvent {
+    /// Adds a transparent layer above the active one.
+    Add,
+    /// Deletes the active layer, it's kept hidden until the step leaves the history.
+    Remove,
+    Raise,
+    Lower,
+    /// Paints the active layer over the one below and deletes it, see [`merge`].
+    MergeDown,
+    Select(usize),
+    SetVisible(usize, bool),
+    SetLocked(usize, bool),
+    SetOpacity(usize, f32),
+    Rename(usize, String),
+}
+
+/// Content of a cell of `upper` drawn over a cell of `lower`, `opacity` being the opacity of the
+/// upper layer or 0 when it's hidden.
+///
+/// A cell only has two colors, so a transparent foreground over another glyph shows the
+/// background of the lower cell, and the upper cell is only drawn if it's at least half opaque.
+pub fn merge(upper: Cell, lower: Cell, opacity: f32) -> Cell {
+    if opacity < 0.5 { return lower; }
+    match (upper.fg == TRANSPARENT, upper.bg == TRANSPARENT) {
+        (true, true) => lower,
+        (false, true) => Cell { bg: lower.bg, ..upper },
+        (true, false) => Cell { fg: lower.bg, ..upper },
+        (false, false) => upper,
+    }
+}
+
+/// Depth and visibility of the entities of the layers.
+#[derive(SystemParam)]
+pub struct LayerStack<'w, 's> {
+    cells: Query<'w, 's, (&'static mut Transform, &'static mut Visibility), With<TileColors>>,
+    quads: Query<'w, 's, &'static mut Visibility, (With<Handle<CanvasMaterial>>, Without<TileColors>)>,
+}
+
+impl<'w, 's> LayerStack<'w, 's> {
+    /// Puts the cells of each layer at the depth of its index, the batched renderer moves the
+    /// quads itself.
+    pub fn restack(&mut self, grid: &Grid) {
+        for (i, layer) in grid.layers().iter().enumerate() {
+            for &e in layer.cells() {
+                if let Ok((mut transform, _)) = self.cells.get_mut(e) {
+                    transform.translation.z = i as f32 * LAYER_Z_STEP;
+                }
+            }
+        }
+    }
+
+    pub fn show(&mut self, layer: &Layer, visible: bool) {
+        for &e in layer.cells() {
+            if let Ok((_, mut visibility)) = self.cells.get_mut(e) { visibility.is_visible = visible; }
+        }
+        if let Some(mut visibility) = layer.quad().and_then(|e| self.quads.get_mut(e).ok()) {
+            visibility.is_visible = visible;
+        }
+    }
+}
+
+/// Adds the removed layer `id` back at `index`, or removes it from the stack.
+pub fn toggle_layer(grid: &mut Grid, stack: &mut LayerStack, id: u32, index: usize, add: bool) {
+    if add {
+        if !grid.attach(id, index) { return; }
+        let layer = grid.active_layer();
+        stack.show(layer, layer.visible);
+    } else {
+        let index = match grid.layer_index(id) {
+            Some(index) => index,
+            None => return,
+        };
+        grid.detach(index);
+        if let Some(layer) = grid.removed().iter().find(|layer| layer.id == id) { stack.show(layer, false); }
+    }
+    stack.restack(grid);
+}
+
+/// Despawns the removed layers whose steps left the history, they can't come back.
+fn despawn_dropped_layers(
+    mut history: ResMut<History>,
+    mut grid: ResMut<Grid>,
+    mut spawner: CellSpawner,
+) {
+    if !history.has_dropped() { return; }
+    for id in history.take_dropped() {
+        if let Some(layer) = grid.take_removed(id) { spawner.despawn(&layer); }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_layer_events(
+    mut events: EventReader<LayerEvent>,
+    mut grid: ResMut<Grid>,
+    mut spawner: CellSpawner,
+    tiles: Res<Tiles>,
+    meshes: Res<BasicMesh>,
+    canvas: Res<Canvas>,
+    mut history: ResMut<History>,
+    mut paint_events: EventWriter<PaintEvent>,
+    cells: Query<(&TileId, &TileColors)>,
+    colored: Query<(&TileColors, &Handle<TileMaterial>)>,
+    mut stack: LayerStack,
+) {
+    for event in events.iter() {
+        let active = grid.active;
+        match event {
+            LayerEvent::Add => {
+                let name = format!("Layer {}", grid.layers().len() + 1);
+                let blank = vec![Cell::transparent(); (canvas.width * canvas.height) as usize];
+                grid.push_layer(&mut spawner, &tiles, &meshes, &canvas, &LayerData::new(&name, blank));
+                let layer = grid.layers_mut().pop().unwrap();
+                let id = layer.id;
+                grid.layers_mut().insert(active + 1, layer);
+                grid.active = active + 1;
+                stack.restack(&grid);
+                history.commit();
+                history.name("Add layer");
+                history.record_layer(id, active + 1, true);
+                history.commit();
+            }
+            LayerEvent::Remove | LayerEvent::MergeDown if grid.layers().len() == 1 => {}
+            LayerEvent::Remove => {
+                history.commit();
+                history.name("Remove layer");
+                let id = grid.layers()[active].id;
+                toggle_layer(&mut grid, &mut stack, id, active, false);
+                history.record_layer(id, active, false);
+                history.commit();
+            }
+            LayerEvent::MergeDown => {
+                if active == 0 || grid.layers()[active - 1].locked { continue; }
+                let (upper, lower) = match (grid.read_layer(active, &cells), grid.read_layer(active - 1, &cells)) {
+                    (Some(upper), Some(lower)) => (upper, lower),
+                    _ => continue,
+                };
+                let layer = &grid.layers()[active];
+                let opacity = if layer.visible { layer.opacity } else { 0. };
+                // The merge is painted on the lower layer and the upper one is removed in the same
+                // step, which is closed once the paint is applied
+                history.commit();
+                history.name("Merge down");
+                for (i, (&upper, &lower)) in upper.iter().zip(&lower).enumerate() {
+                    let pos = TilePos { x: i as u32 % canvas.width, y: i as u32 / canvas.width };
+                    paint_events.send(PaintEvent { pos, cell: merge(upper, lower, opacity) });
+                }
+                let id = grid.layers()[active].id;
+                toggle_layer(&mut grid, &mut stack, id, active, false);
+                history.record_layer(id, active, false);
+                grid.active = active - 1;
+            }
+            LayerEvent::Raise | LayerEvent::Lower => {
+                let (index, name) = match event {
+                    LayerEvent::Raise if active + 1 < grid.layers().len() => (active + 1, "Raise layer"),
+                    LayerEvent::Lower if active > 0 => (active - 1, "Lower layer"),
+                    _ => continue,
+                };
+                // The layer leaves its place and comes back at the new one, so the indices of the
+                // later steps stay valid when this one is undone
+                history.commit();
+                history.name(name);
+                let id = grid.layers()[active].id;
+                toggle_layer(&mut grid, &mut stack, id, active, false);
+                toggle_layer(&mut grid, &mut stack, id, index, true);
+                history.record_layer(id, active, false);
+                history.record_layer(id, index, true);
+                history.commit();
+            }
+            LayerEvent::Select(i) => grid.active = (*i).min(grid.layers().len() - 1),
+            LayerEvent::SetVisible(i, visible) => {
+                if let Some(layer) = grid.layers_mut().get_mut(*i) {
+                    layer.visible = *visible;
+                    stack.show(layer, *visible);
+                }
+            }
+            LayerEvent::SetLocked(i, locked) => {
+                if let Some(layer) = grid.layers_mut().get_mut(*i) { layer.locked = *locked; }
+            }
+            LayerEvent::SetOpacity(i, opacity) => {
+                if let Some(layer) = grid.layers_mut().get_mut(*i) {
+                    layer.opacity = opacity.clamp(0., 1.);
+                    spawner.recolor(layer, &colored);
+                }
+            }
+            LayerEvent::Rename(i, name) => {
+                if let Some(layer) = grid.layers_mut().get_mut(*i) { layer.name = name.clone(); }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(index: usize, fg: usize, bg: usize) -> Cell {
+        Cell { id: TileId { index, ..TileId::new() }, fg, bg }
+    }
+
+    #[test]
+    fn merge_shows_the_lower_cell_through_transparent_colors() {
+        let lower = cell(1, 2, 3);
+        assert_eq!(merge(cell(4, 5, 6), lower, 1.), cell(4, 5, 6));
+        assert_eq!(merge(Cell::transparent(), lower, 1.), lower);
+        assert_eq!(merge(cell(4, 5, TRANSPARENT), lower, 1.), cell(4, 5, 3));
+        // The lower glyph is covered, only its background can show
+        assert_eq!(merge(cell(4, TRANSPARENT, 6), lower, 1.), cell(4, 3, 6));
+    }
+
+    #[test]
+    fn merge_rounds_the_opacity() {
+        let (upper, lower) = (cell(4, 5, 6), cell(1, 2, 3));
+        assert_eq!(merge(upper, lower, 0.6), upper);
+        assert_eq!(merge(upper, lower, 0.3), lower);
+        assert_eq!(merge(cell(4, 5, TRANSPARENT), lower, 0.5), cell(4, 5, 3));
+    }
+
+    #[test]
+    fn merge_skips_a_hidden_layer() {
+        let lower = cell(1, 2, 3);
+        assert_eq!(merge(cell(4, 5, 6), lower, 0.), lower);
+        assert_eq!(merge(Cell::transparent(), Cell::transparent(), 0.), Cell::transparent());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,460 @@
+use bevy::ecs::system::SystemParam;
+use bevy::math::vec2;
+use bevy::prelude::*;
+use bevy::sprite::{MaterialMesh2dBundle, Mesh2dHandle};
+use bevy::window::PresentMode;
+use bevy_textmode::{BasicMesh, Canvas, CanvasMaterial, Cell, Colors, init_spritesheet, TextModeBundle, TextModePlugin, TileColors, TileId, TileMaterial, TilePos, Tiles};
+use crate::config::Config;
+use crate::cursor::{CursorPlugin, TileCursor};
+use crate::gui::GuiPlugin;
+use crate::paint::PaintPlugin;
+use crate::document::{CurrentDocument, DocumentPlugin};
+use crate::history::{History, HistoryPlugin};
+use crate::export::ExportPlugin;
+use crate::import::ImportPlugin;
+use crate::palette::PalettePlugin;
+use crate::fill::FillPlugin;
+use crate::shapes::ShapePlugin;
+use crate::box_drawing::BoxDrawingPlugin;
+use crate::selection::SelectionPlugin;
+use crate::eyedropper::EyedropperPlugin;
+use crate::camera::{CameraEvent, CameraPlugin};
+use crate::overlay::OverlayPlugin;
+use crate::layers::{LAYER_Z_STEP, LayerData, LayersPlugin};
+use crate::renderer::{CanvasCell, recolor_layer, Renderer, RendererPlugin};
+use crate::text::TextPlugin;
+
+mod gui;
+mod cursor;
+mod paint;
+mod document;
+mod history;
+mod export;
+mod import;
+mod config;
+mod palette;
+mod fill;
+mod shapes;
+mod box_drawing;
+mod selection;
+mod eyedropper;
+mod camera;
+mod overlay;
+mod layers;
+mod renderer;
+mod text;
+
+fn main() {
+    let config = Config::from_env().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    // Any palette format, the library only reads images
+    let colors = palette::load(&config.palette).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugin(TextModePlugin::builder(&config.tileset, config.tile_size, &config.palette)
+            .colors(colors)
+            .size(config.width, config.height)
+            .offset(vec2(26.0, 0.0))
+            .build())
+        .add_plugin(GuiPlugin)
+        .add_plugin(CursorPlugin)
+        .add_plugin(PaintPlugin)
+        .add_plugin(DocumentPlugin)
+        .add_plugin(HistoryPlugin)
+        .add_plugin(ExportPlugin)
+        .add_plugin(ImportPlugin)
+        .add_plugin(PalettePlugin)
+        .add_plugin(FillPlugin)
+        .add_plugin(ShapePlugin)
+        .add_plugin(BoxDrawingPlugin)
+        .add_plugin(SelectionPlugin)
+        .add_plugin(EyedropperPlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(OverlayPlugin)
+        .add_plugin(LayersPlugin)
+        .add_plugin(RendererPlugin)
+        .add_plugin(TextPlugin)
+        .insert_resource(config.renderer)
+        .insert_resource(WindowDescriptor {
+            title: "bevy_textmode".to_string(),
+            present_mode: PresentMode::Immediate,
+            ..Default::default()
+        })
+        .add_event::<NewCanvas>()
+        .add_startup_system(setup)
+        .add_system(new_canvas)
+        .add_startup_stage(
+            "game_setup_grid",
+            SystemStage::single(spawn_grid),
+        )
+        .run();
+}
+
+#[derive(Component)]
+struct MainCamera;
+
+/// Cell entities of the canvas, one grid per layer.
+pub struct Grid {
+    width: u32,
+    height: u32,
+    /// Bottom layer first.
+    layers: Vec<Layer>,
+    /// Index of the layer edited by the tools.
+    pub active: usize,
+    next_id: u32,
+    /// Layers deleted by steps of the history, hidden until the step is undone or forgotten.
+    removed: Vec<Layer>,
+}
+
+/// A full grid of cells drawn over the layers below it.
+pub struct Layer {
+    /// Identifies the layer in the history, unlike its index it doesn't change with reordering.
+    pub id: u32,
+    pub name: String,
+    pub visible: bool,
+    pub locked: bool,
+    pub opacity: f32,
+    /// Cell entities, indexed by `x + y * width`.
+    cells: Vec<Entity>,
+    /// Entity drawing the cells with [`Renderer::Batched`].
+    quad: Option<Entity>,
+}
+
+impl Layer {
+    pub fn cells(&self) -> &[Entity] {
+        &self.cells
+    }
+
+    pub fn quad(&self) -> Option<Entity> {
+        self.quad
+    }
+}
+
+impl Grid {
+    /// Cell entity of the active layer.
+    pub fn get(&self, pos: &TilePos) -> Option<Entity> {
+        self.get_in(self.active, pos)
+    }
+
+    pub fn get_in(&self, layer: usize, pos: &TilePos) -> Option<Entity> {
+        if pos.x >= self.width || pos.y >= self.height { return None; }
+        self.layers.get(layer)?.cells.get((pos.x + pos.y * self.width) as usize).copied()
+    }
+
+    /// Content of every cell of the active layer, indexed by `x + y * width`.
+    pub fn read(&self, cells: &Query<(&TileId, &TileColors)>) -> Option<Vec<Cell>> {
+        self.read_layer(self.active, cells)
+    }
+
+    pub fn read_layer(&self, layer: usize, cells: &Query<(&TileId, &TileColors)>) -> Option<Vec<Cell>> {
+        self.layers.get(layer)?.cells.iter()
+            .map(|&e| cells.get(e).ok().map(|(id, c)| Cell { id: *id, fg: c.fg, bg: c.bg }))
+            .collect()
+    }
+
+    /// Content and settings of every layer, bottom first.
+    pub fn read_layers(&self, cells: &Query<(&TileId, &TileColors)>) -> Option<Vec<LayerData>> {
+        self.layers.iter().enumerate()
+            .map(|(i, layer)| Some(LayerData {
+                name: layer.name.clone(),
+                visible: layer.visible,
+                locked: layer.locked,
+                opacity: layer.opacity,
+                cells: self.read_layer(i, cells)?,
+            }))
+            .collect()
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut Vec<Layer> {
+        &mut self.layers
+    }
+
+    pub fn active_layer(&self) -> &Layer {
+        &self.layers[self.active]
+    }
+
+    pub fn layer_index(&self, id: u32) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.id == id)
+    }
+
+    /// Moves the layer at `index` to the removed layers and returns its id.
+    pub fn detach(&mut self, index: usize) -> u32 {
+        let layer = self.layers.remove(index);
+        let id = layer.id;
+        self.removed.push(layer);
+        if self.active > index || self.active == self.layers.len() { self.active = self.active.saturating_sub(1); }
+        id
+    }
+
+    /// Puts a removed layer back at `index` and makes it active.
+    pub fn attach(&mut self, id: u32, index: usize) -> bool {
+        let layer = match self.removed.iter().position(|layer| layer.id == id) {
+            Some(i) => self.removed.remove(i),
+            None => return false,
+        };
+        let index = index.min(self.layers.len());
+        self.layers.insert(index, layer);
+        self.active = index;
+        true
+    }
+
+    /// Takes a removed layer out of the grid for good, to despawn it.
+    pub fn take_removed(&mut self, id: u32) -> Option<Layer> {
+        let i = self.removed.iter().position(|layer| layer.id == id)?;
+        Some(self.removed.remove(i))
+    }
+
+    pub fn removed(&self) -> &[Layer] {
+        &self.removed
+    }
+
+    /// Content of erased cells: the bottom layer is opaque, the others let the layers below show.
+    pub fn blank(&self) -> Cell {
+        if self.active == 0 { Cell::empty() } else { Cell::transparent() }
+    }
+
+    /// Spawns a layer above the others.
+    pub(crate) fn push_layer(&mut self, spawner: &mut CellSpawner, tiles: &Tiles, meshes: &BasicMesh, canvas: &Canvas, data: &LayerData) {
+        let z = self.layers.len() as f32 * LAYER_Z_STEP;
+        let (cells, quad) = spawner.spawn(tiles, meshes, canvas, data, z);
+        self.layers.push(Layer {
+            id: self.next_id,
+            name: data.name.clone(),
+            visible: data.visible,
+            locked: data.locked,
+            opacity: data.opacity,
+            cells,
+            quad,
+        });
+        self.next_id += 1;
+    }
+}
+
+/// Replaces the canvas and its content, reloading the tileset if it changed.
+pub struct NewCanvas {
+    pub tileset: String,
+    pub tile_size: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Layers from the bottom, a single empty layer if `None`.
+    pub layers: Option<Vec<LayerData>>,
+    /// Replaces the palette and the file it's saved to, once the layers are known to be valid.
+    pub palette: Option<(Colors, String)>,
+    /// Document the canvas is saved to once it's replaced.
+    pub path: Option<String>,
+    /// Status shown once the canvas is replaced.
+    pub status: String,
+}
+
+fn setup(
+    mut commands: Commands,
+    canvas: Res<Canvas>,
+) {
+    let mut camera = OrthographicCameraBundle::new_2d();
+    camera.transform = Transform {
+        translation: canvas.center().extend(999.0),
+        scale: Vec3::new(0.25, 0.25, 1.0),
+        ..Default::default()
+    };
+    commands
+        .spawn_bundle(camera)
+        .insert(MainCamera);
+}
+
+fn spawn_grid(
+    mut commands: Commands,
+    mut spawner: CellSpawner,
+    tiles: Res<Tiles>,
+    meshes: Res<BasicMesh>,
+    canvas: Res<Canvas>,
+) {
+    let cells = vec![Cell::empty(); (canvas.width * canvas.height) as usize];
+    let grid = spawn_cells(&mut spawner, &tiles, &meshes, &canvas, &[LayerData::new("Background", cells)]);
+    commands.insert_resource(grid);
+}
+
+/// Spawns and despawns the cell entities of layers.
+#[derive(SystemParam)]
+pub struct CellSpawner<'w, 's> {
+    commands: Commands<'w, 's>,
+    materials: ResMut<'w, Assets<TileMaterial>>,
+    canvas_materials: ResMut<'w, Assets<CanvasMaterial>>,
+    colors: ResMut<'w, Colors>,
+    renderer: Res<'w, Renderer>,
+}
+
+impl<'w, 's> CellSpawner<'w, 's> {
+    /// Spawns one entity per cell of `layer` at depth `z`, and the quad drawing them with the
+    /// batched renderer.
+    fn spawn(&mut self, tiles: &Tiles, meshes: &BasicMesh, canvas: &Canvas, layer: &LayerData, z: f32) -> (Vec<Entity>, Option<Entity>) {
+        match *self.renderer {
+            Renderer::Batched => {
+                let (cells, quad) = self.spawn_batched(tiles, meshes, canvas, layer, z);
+                (cells, Some(quad))
+            }
+            Renderer::Entities => (self.spawn_entities(tiles, meshes, canvas, layer, z), None),
+        }
+    }
+
+    fn spawn_batched(&mut self, tiles: &Tiles, meshes: &BasicMesh, canvas: &Canvas, layer: &LayerData, z: f32) -> (Vec<Entity>, Entity) {
+        let palette = self.colors.iter().copied().collect::<Vec<Color>>();
+        let material = CanvasMaterial::new(tiles.atlas.clone(), canvas.tile_size, canvas.width, canvas.height, &palette, layer.opacity);
+        let mut cells = vec![];
+        for (i, cell) in layer.cells.iter().enumerate() {
+            let pos = TilePos { x: i as u32 % canvas.width, y: i as u32 / canvas.width };
+            let colors = TileColors { fg: cell.fg, bg: cell.bg };
+            material.set(pos.x, pos.y, &cell.id, &colors);
+            cells.push((pos, cell.id, colors));
+        }
+        let material = self.canvas_materials.add(material);
+        let cells = cells.into_iter()
+            .map(|cell| self.commands.spawn_bundle(cell).insert(CanvasCell { material: material.clone() }).id())
+            .collect();
+
+        let tile = canvas.tile_size as f32;
+        let quad = self.commands
+            .spawn_bundle(MaterialMesh2dBundle {
+                mesh: meshes.canvas.clone().into(),
+                material,
+                transform: Transform {
+                    translation: (canvas.center() + canvas.offset).extend(z),
+                    scale: Vec3::new(canvas.width as f32 * tile, canvas.height as f32 * tile, 1.),
+                    ..Default::default()
+                },
+                visibility: Visibility { is_visible: layer.visible },
+                ..Default::default()
+            })
+            .id();
+        (cells, quad)
+    }
+
+    fn spawn_entities(&mut self, tiles: &Tiles, meshes: &BasicMesh, canvas: &Canvas, layer: &LayerData, z: f32) -> Vec<Entity> {
+        let mut entities = vec![];
+        for y in 0..canvas.height {
+            for x in 0..canvas.width {
+                let cell = layer.cells[(x + y * canvas.width) as usize];
+                let mut bundle = TextModeBundle::new(
+                    tiles, &mut self.materials,
+                    &cell.id,
+                    x, y,
+                    self.colors.faded(cell.bg, layer.opacity), self.colors.faded(cell.fg, layer.opacity),
+                    meshes.tile.clone(), canvas
+                );
+                bundle.transform.translation.z = z;
+                bundle.visibility.is_visible = layer.visible;
+                entities.push(self.commands
+                    .spawn_bundle(bundle)
+                    .insert(TileColors { fg: cell.fg, bg: cell.bg })
+                    .id());
+            }
+        }
+        entities
+    }
+
+    /// Replaces the palette before spawning the layers of a new canvas.
+    fn set_colors(&mut self, colors: Colors) {
+        *self.colors = colors;
+    }
+
+    pub fn despawn(&mut self, layer: &Layer) {
+        layer.cells.iter().chain(&layer.quad).for_each(|&e| self.commands.entity(e).despawn());
+    }
+
+    /// Updates the materials of `layer` after a change of its opacity.
+    pub fn recolor(&mut self, layer: &Layer, cells: &Query<(&TileColors, &Handle<TileMaterial>)>) {
+        recolor_layer(layer, &self.colors, &mut self.materials, cells);
+    }
+}
+
+/// Spawns the cells of every layer, the top one being active.
+fn spawn_cells(spawner: &mut CellSpawner, tiles: &Tiles, meshes: &BasicMesh, canvas: &Canvas, layers: &[LayerData]) -> Grid {
+    let mut grid = Grid { width: canvas.width, height: canvas.height, layers: vec![], active: 0, next_id: 0, removed: vec![] };
+    layers.iter().for_each(|layer| grid.push_layer(spawner, tiles, meshes, canvas, layer));
+    grid.active = grid.layers.len() - 1;
+    grid
+}
+
+#[allow(clippy::too_many_arguments)]
+fn new_canvas(
+    mut spawner: CellSpawner,
+    mut events: EventReader<NewCanvas>,
+    mut canvas: ResMut<Canvas>,
+    mut grid: ResMut<Grid>,
+    mut tiles: ResMut<Tiles>,
+    mut meshes: ResMut<BasicMesh>,
+    mut images: ResMut<Assets<Image>>,
+    mut mesh_assets: ResMut<Assets<Mesh>>,
+    mut history: ResMut<History>,
+    mut document: ResMut<CurrentDocument>,
+    mut camera_events: EventWriter<CameraEvent>,
+    mut cursor: Query<&mut Mesh2dHandle, With<TileCursor>>,
+) {
+    for event in events.iter() {
+        if event.width == 0 || event.height == 0 {
+            document.status = Some("Canvas dimensions must be positive".to_string());
+            continue;
+        }
+
+        let new_tiles = if event.tileset != canvas.tileset || event.tile_size != canvas.tile_size {
+            match init_spritesheet(&event.tileset, event.tile_size, &mut images) {
+                Ok(new_tiles) => Some(new_tiles),
+                Err(error) => {
+                    document.status = Some(error);
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+
+        let size = (event.width * event.height) as usize;
+        let layers = event.layers.clone()
+            .unwrap_or_else(|| vec![LayerData::new("Background", vec![Cell::empty(); size])]);
+        let available = new_tiles.as_ref().unwrap_or(&tiles);
+        let valid = |layer: &LayerData| layer.cells.len() == size
+            && layer.cells.iter().all(|c| available.contains(&c.id));
+        if layers.is_empty() || !layers.iter().all(valid) {
+            document.status = Some("Canvas content doesn't match the tileset".to_string());
+            continue;
+        }
+        let palette: &Colors = event.palette.as_ref().map_or(&spawner.colors, |(colors, _)| colors);
+        if !layers.iter().flat_map(|layer| &layer.cells).all(|c| palette.contains(c.fg) && palette.contains(c.bg)) {
+            document.status = Some("Canvas content doesn't match the palette".to_string());
+            continue;
+        }
+        if let Some((palette, path)) = &event.palette {
+            spawner.set_colors(palette.clone());
+            canvas.palette = path.clone();
+        }
+
+        if let Some(new_tiles) = new_tiles {
+            // Materials still using the old atlas are replaced below or follow the new one
+            images.remove(&tiles.atlas);
+            *tiles = new_tiles;
+            *meshes = BasicMesh::new(event.tile_size, &mut mesh_assets);
+            cursor.for_each_mut(|mut mesh| *mesh = meshes.tile.clone().into());
+            canvas.tileset = event.tileset.clone();
+            canvas.tile_size = event.tile_size;
+        }
+
+        grid.layers.iter().chain(&grid.removed).for_each(|layer| spawner.despawn(layer));
+        history.clear();
+        canvas.width = event.width;
+        canvas.height = event.height;
+        *grid = spawn_cells(&mut spawner, &tiles, &meshes, &canvas, &layers);
+        camera_events.send(CameraEvent::Fit);
+        if let Some(path) = &event.path { document.path_input = path.clone(); }
+        document.path = event.path.clone();
+        document.status = Some(event.status.clone());
+    }
+}
\ No newline at end of file
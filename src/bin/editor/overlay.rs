@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_textmode::Canvas;
+use crate::camera::View;
+use crate::cursor::HoveredTile;
+
+/// Above the tiles and previews, below the cursor.
+const OVERLAY_Z: f32 = 5.;
+const MINOR_COLOR: Color = Color::rgba(1., 1., 1., 0.12);
+const MAJOR_COLOR: Color = Color::rgba(1., 1., 1., 0.35);
+const GUIDE_COLOR: Color = Color::rgba(0., 0.9, 1., 0.8);
+
+pub struct OverlayPlugin;
+
+impl Plugin for OverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(Overlay {
+                grid: false,
+                major: 8,
+                show_guides: true,
+                guides: vec![],
+            })
+            .add_system(overlay_shortcuts)
+            .add_system(draw_overlay.after(overlay_shortcuts));
+    }
+}
+
+/// A line between two rows or columns of cells.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct Guide {
+    pub vertical: bool,
+    /// Number of columns left of the line, or rows below it.
+    pub pos: u32,
+}
+
+pub struct Overlay {
+    /// Lines between every cell.
+    pub grid: bool,
+    /// Highlight every `major` grid line, 0 to disable.
+    pub major: u32,
+    pub show_guides: bool,
+    pub guides: Vec<Guide>,
+}
+
+impl Overlay {
+    /// Adds a guide, or removes it if it already exists.
+    pub fn toggle_guide(&mut self, guide: Guide) {
+        match self.guides.iter().position(|g| *g == guide) {
+            Some(i) => { self.guides.remove(i); }
+            None => self.guides.push(guide),
+        }
+    }
+}
+
+/// G toggles the grid, H the guides. Shift+H and Shift+V add a guide at the hovered cell.
+fn overlay_shortcuts(
+    mut egui_ctx: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    hovered: Res<HoveredTile>,
+    mut overlay: ResMut<Overlay>,
+) {
+    if egui_ctx.ctx_mut().wants_keyboard_input() { return; }
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    let shift = keys.any_pressed([KeyCode::LShift, KeyCode::RShift]);
+    if ctrl { return; }
+    if keys.just_pressed(KeyCode::G) {
+        overlay.grid = !overlay.grid;
+    } else if shift && keys.just_pressed(KeyCode::H) {
+        if let Some(pos) = hovered.pos { overlay.toggle_guide(Guide { vertical: false, pos: pos.y }); }
+    } else if shift && keys.just_pressed(KeyCode::V) {
+        if let Some(pos) = hovered.pos { overlay.toggle_guide(Guide { vertical: true, pos: pos.x }); }
+    } else if keys.just_pressed(KeyCode::H) {
+        overlay.show_guides = !overlay.show_guides;
+    }
+}
+
+#[derive(Component)]
+struct OverlayLine;
+
+/// Rebuilds the overlay lines, one screen pixel thick at the current zoom.
+fn draw_overlay(
+    mut commands: Commands,
+    overlay: Res<Overlay>,
+    canvas: Res<Canvas>,
+    view: Res<View>,
+    lines: Query<Entity, With<OverlayLine>>,
+) {
+    if !overlay.is_changed() && !canvas.is_changed() && !view.is_changed() { return; }
+    lines.iter().for_each(|e| commands.entity(e).despawn());
+
+    let tile = canvas.tile_size as f32;
+    let thickness = view.scale();
+    let (width, height) = (canvas.width as f32 * tile, canvas.height as f32 * tile);
+    // Bottom left corner of the canvas, cells are centered on their position
+    let origin = canvas.offset - Vec2::splat(tile / 2.);
+
+    let mut line = |guide: Guide, color: Color, z: f32| {
+        let (size, center) = if guide.vertical {
+            (Vec2::new(thickness, height), origin + Vec2::new(guide.pos as f32 * tile, height / 2.))
+        } else {
+            (Vec2::new(width, thickness), origin + Vec2::new(width / 2., guide.pos as f32 * tile))
+        };
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite { color, custom_size: Some(size), ..Default::default() },
+                transform: Transform::from_translation(center.extend(z)),
+                ..Default::default()
+            })
+            .insert(OverlayLine);
+    };
+
+    if overlay.grid {
+        let columns = (0..=canvas.width).map(|pos| Guide { vertical: true, pos });
+        let rows = (0..=canvas.height).map(|pos| Guide { vertical: false, pos });
+        for guide in columns.chain(rows) {
+            let major = overlay.major > 0 && guide.pos % overlay.major == 0;
+            line(guide, if major { MAJOR_COLOR } else { MINOR_COLOR }, OVERLAY_Z);
+        }
+    }
+
+    if overlay.show_guides {
+        for &guide in &overlay.guides {
+            let limit = if guide.vertical { canvas.width } else { canvas.height };
+            if guide.pos <= limit { line(guide, GUIDE_COLOR, OVERLAY_Z + 0.1); }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,144 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_textmode::{Canvas, Cell, Colors, TileColors, TileId, TileMaterial, TilePos, Tiles};
+use crate::Grid;
+use crate::cursor::{HoveredTile, TileCursor};
+use crate::eyedropper::pick;
+use crate::gui::{Tool, UiState};
+use crate::history::History;
+use crate::selection::{Block, stamp};
+
+pub struct PaintPlugin;
+
+impl Plugin for PaintPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<PaintEvent>()
+            .add_system(paint.after(pick))
+            .add_system(apply_paint.after(paint))
+            .add_system(end_stroke.after(apply_paint));
+    }
+}
+
+/// Request to write `cell` at `pos`.
+pub struct PaintEvent {
+    pub pos: TilePos,
+    pub cell: Cell,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn paint(
+    mut egui_ctx: ResMut<EguiContext>,
+    mouse: Res<Input<MouseButton>>,
+    hovered: Res<HoveredTile>,
+    ui_state: Res<UiState>,
+    canvas: Res<Canvas>,
+    grid: Res<Grid>,
+    mut last: Local<Option<TilePos>>,
+    mut events: EventWriter<PaintEvent>,
+) {
+    if !mouse.pressed(MouseButton::Left) && !mouse.pressed(MouseButton::Right) {
+        *last = None;
+        return;
+    }
+    if ui_state.tool != Tool::Pencil { return; }
+    if egui_ctx.ctx_mut().is_pointer_over_area() { return; }
+    let pos = match hovered.pos {
+        Some(pos) => pos,
+        None => return,
+    };
+    // Only paint once per cell while dragging
+    if *last == Some(pos) { return; }
+    *last = Some(pos);
+
+    if let Some(brush) = &ui_state.brush {
+        // The brush is held by its top left cell
+        let origin = (pos.x as i32, pos.y as i32 - brush.height as i32 + 1);
+        if mouse.pressed(MouseButton::Left) {
+            stamp(brush, origin, &canvas, &mut events);
+        } else {
+            let empty = Block { width: brush.width, height: brush.height, cells: vec![grid.blank(); brush.cells.len()] };
+            stamp(&empty, origin, &canvas, &mut events);
+        }
+    } else if mouse.pressed(MouseButton::Left) {
+        events.send(PaintEvent {
+            pos,
+            cell: Cell { id: ui_state.tile_id, fg: ui_state.fg, bg: ui_state.bg },
+        });
+    } else if mouse.pressed(MouseButton::Right) {
+        events.send(PaintEvent { pos, cell: grid.blank() });
+    }
+}
+
+/// Cells drawn by the batched renderer have no material.
+type WritableCell = (&'static mut TileId, &'static mut TileColors, Option<&'static Handle<TileMaterial>>);
+
+/// Reads and writes grid cells, keeping their [`TileMaterial`] in sync when they have one.
+#[derive(SystemParam)]
+pub struct CellWriter<'w, 's> {
+    materials: ResMut<'w, Assets<TileMaterial>>,
+    tiles: Res<'w, Tiles>,
+    colors: Res<'w, Colors>,
+    grid: Res<'w, Grid>,
+    cells: Query<'w, 's, WritableCell, Without<TileCursor>>,
+}
+
+impl<'w, 's> CellWriter<'w, 's> {
+    /// Writes `cell` at `pos` on the active layer and returns the previous content.
+    pub fn set(&mut self, pos: &TilePos, cell: &Cell) -> Option<Cell> {
+        self.set_in(self.grid.active, pos, cell)
+    }
+
+    /// Writes `cell` on the layer with the given id, used to replay the history.
+    pub fn set_layer(&mut self, id: u32, pos: &TilePos, cell: &Cell) -> Option<Cell> {
+        let layer = self.grid.layer_index(id)?;
+        self.set_in(layer, pos, cell)
+    }
+
+    pub fn set_in(&mut self, layer: usize, pos: &TilePos, cell: &Cell) -> Option<Cell> {
+        if !self.tiles.contains(&cell.id) { return None; }
+        let opacity = self.grid.layers().get(layer)?.opacity;
+        let (mut id, mut tile_colors, handle) = self.cells.get_mut(self.grid.get_in(layer, pos)?).ok()?;
+        let previous = Cell { id: *id, fg: tile_colors.fg, bg: tile_colors.bg };
+        if previous == *cell { return Some(previous); }
+
+        *id = cell.id;
+        *tile_colors = TileColors { fg: cell.fg, bg: cell.bg };
+        if let Some(material) = handle.and_then(|handle| self.materials.get_mut(handle)) {
+            material.id = cell.id;
+            material.fg = self.colors.faded(cell.fg, opacity);
+            material.bg = self.colors.faded(cell.bg, opacity);
+        }
+        Some(previous)
+    }
+}
+
+/// Writes the painted cells on the active layer, unless it's locked.
+pub fn apply_paint(
+    mut events: EventReader<PaintEvent>,
+    mut writer: CellWriter,
+    grid: Res<Grid>,
+    mut history: ResMut<History>,
+) {
+    let layer = grid.active_layer();
+    if layer.locked {
+        events.iter().for_each(drop);
+        return;
+    }
+    for event in events.iter() {
+        if let Some(before) = writer.set(&event.pos, &event.cell) {
+            history.record(layer.id, event.pos, before, event.cell);
+        }
+    }
+}
+
+/// Edits made while a mouse button is held are grouped in a single history step.
+fn end_stroke(
+    mouse: Res<Input<MouseButton>>,
+    mut history: ResMut<History>,
+) {
+    if !mouse.pressed(MouseButton::Left) && !mouse.pressed(MouseButton::Right) {
+        history.commit();
+    }
+}
\ No newline at end of file
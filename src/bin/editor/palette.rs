@@ -0,0 +1,339 @@
+use std::fs;
+use std::path::Path;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_textmode::{Canvas, Cell, Colors, TileColors, to_hex};
+use crate::gui::UiState;
+use crate::history::History;
+use crate::selection::Selection;
+
+/// Largest palette accepted on import.
+pub const MAX_COLORS: usize = 256;
+
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(PaletteFile {
+                path: "palette.gpl".to_string(),
+                status: None,
+            })
+            .add_event::<PaletteEvent>()
+            .add_system(handle_palette_events);
+    }
+}
+
+/// Palette file formats, chosen from the file extension.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PaletteFormat {
+    /// GIMP `.gpl`
+    Gimp,
+    /// JASC (Paint Shop Pro) `.pal`
+    Jasc,
+    /// Lospec `.hex`, one `rrggbb` color per line
+    Hex,
+    /// Paint.NET `.txt`, one `aarrggbb` color per line
+    PaintNet,
+    /// Image whose colors are read in reading order, see [`Colors::from_image`]
+    Png,
+}
+
+impl PaletteFormat {
+    pub fn from_path(path: &str) -> Result<Self, String> {
+        let extension = Path::new(path).extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("gpl") => Ok(PaletteFormat::Gimp),
+            Some("pal") => Ok(PaletteFormat::Jasc),
+            Some("hex") => Ok(PaletteFormat::Hex),
+            Some("txt") => Ok(PaletteFormat::PaintNet),
+            Some("png") => Ok(PaletteFormat::Png),
+            _ => Err(format!("Unknown palette format for {} (expected .gpl, .pal, .hex, .txt or .png)", path)),
+        }
+    }
+}
+
+pub fn load(path: &str) -> Result<Colors, String> {
+    let colors = match PaletteFormat::from_path(path)? {
+        PaletteFormat::Png => Colors::from_image(path)?,
+        format => {
+            let text = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+            Colors::new(parse(format, &text)?)?
+        }
+    };
+    if colors.len() > MAX_COLORS {
+        return Err(format!("Too many colors ({}, at most {})", colors.len(), MAX_COLORS));
+    }
+    Ok(colors)
+}
+
+/// File written by [`PaletteEvent::Save`]: the configured palette, or a `.gpl` next to it when
+/// it's an image since those are usually swatch grids made by hand.
+pub fn save_path(palette: &str) -> String {
+    match PaletteFormat::from_path(palette) {
+        Ok(PaletteFormat::Png) | Err(_) => Path::new(palette).with_extension("gpl").to_string_lossy().into_owned(),
+        Ok(_) => palette.to_string(),
+    }
+}
+
+pub fn save(path: &str, colors: &Colors) -> Result<(), String> {
+    let format = PaletteFormat::from_path(path)?;
+    if format == PaletteFormat::Png {
+        let mut img = image::RgbaImage::new(colors.len() as u32, 1);
+        for (i, color) in colors.iter().enumerate() {
+            img.put_pixel(i as u32, 0, image::Rgba(color.as_rgba_f32().map(|c| (c * 255.).round() as u8)));
+        }
+        return img.save(path).map_err(|e| format!("Couldn't write {}: {}", path, e));
+    }
+    fs::write(path, write(format, colors)).map_err(|e| format!("Couldn't write {}: {}", path, e))
+}
+
+pub fn parse(format: PaletteFormat, text: &str) -> Result<Vec<Color>, String> {
+    let mut colors = vec![];
+    let mut lines = text.lines().enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    match format {
+        PaletteFormat::Gimp => {
+            match lines.next() {
+                Some((_, "GIMP Palette")) => {}
+                _ => return Err("Missing \"GIMP Palette\" header".to_string()),
+            }
+            for (n, line) in lines {
+                if line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") { continue; }
+                let rgb = line.split_whitespace().take(3).collect::<Vec<&str>>();
+                colors.push(parse_rgb(n, &rgb)?);
+            }
+        }
+        PaletteFormat::Jasc => {
+            match (lines.next(), lines.next()) {
+                (Some((_, "JASC-PAL")), Some((_, "0100"))) => {}
+                _ => return Err("Missing \"JASC-PAL\" header".to_string()),
+            }
+            let (n, count) = lines.next().ok_or_else(|| "Missing color count".to_string())?;
+            let count = count.parse::<usize>().map_err(|_| format!("Line {}: invalid color count {}", n, count))?;
+            for (n, line) in lines {
+                let rgb = line.split_whitespace().collect::<Vec<&str>>();
+                colors.push(parse_rgb(n, &rgb)?);
+            }
+            if colors.len() != count {
+                return Err(format!("Expected {} colors, found {}", count, colors.len()));
+            }
+        }
+        PaletteFormat::Hex => {
+            for (n, line) in lines {
+                colors.push(parse_hex(n, line.trim_start_matches('#'))?);
+            }
+        }
+        PaletteFormat::PaintNet => {
+            for (n, line) in lines {
+                if line.starts_with(';') { continue; }
+                // Colors are written as AARRGGBB, alpha is ignored
+                if line.len() != 8 { return Err(format!("Line {}: invalid color {}", n, line)); }
+                colors.push(parse_hex(n, line.get(2..).unwrap_or(line))?);
+            }
+        }
+        PaletteFormat::Png => return Err("PNG palettes aren't text files".to_string()),
+    }
+
+    if colors.is_empty() { return Err("The palette is empty".to_string()); }
+    if colors.len() > MAX_COLORS {
+        return Err(format!("Too many colors ({}, at most {})", colors.len(), MAX_COLORS));
+    }
+    Ok(colors)
+}
+
+pub fn write(format: PaletteFormat, colors: &Colors) -> String {
+    let rgb = |c: &Color| c.as_rgba_f32().map(|c| (c * 255.).round() as u8);
+    let mut text = String::new();
+    match format {
+        PaletteFormat::Gimp => {
+            text.push_str("GIMP Palette\nName: bevy_textmode\nColumns: 0\n#\n");
+            colors.iter().map(rgb).for_each(|[r, g, b, _]| text.push_str(&format!("{:3} {:3} {:3}\n", r, g, b)));
+        }
+        PaletteFormat::Jasc => {
+            text.push_str(&format!("JASC-PAL\n0100\n{}\n", colors.len()));
+            colors.iter().map(rgb).for_each(|[r, g, b, _]| text.push_str(&format!("{} {} {}\n", r, g, b)));
+        }
+        PaletteFormat::Hex => {
+            colors.iter().for_each(|&c| text.push_str(&format!("{}\n", to_hex(c))));
+        }
+        PaletteFormat::PaintNet => {
+            text.push_str(";paint.net Palette File\n");
+            colors.iter().for_each(|&c| text.push_str(&format!("FF{}\n", to_hex(c).to_uppercase())));
+        }
+        PaletteFormat::Png => {}
+    }
+    text
+}
+
+fn parse_rgb(line: usize, rgb: &[&str]) -> Result<Color, String> {
+    if rgb.len() != 3 { return Err(format!("Line {}: expected 3 components", line)); }
+    let mut channels = [0u8; 3];
+    for (channel, text) in channels.iter_mut().zip(rgb) {
+        *channel = text.parse().map_err(|_| format!("Line {}: invalid color component {}", line, text))?;
+    }
+    Ok(Color::rgb_u8(channels[0], channels[1], channels[2]))
+}
+
+fn parse_hex(line: usize, hex: &str) -> Result<Color, String> {
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Line {}: invalid hex color {}", line, hex));
+    }
+    Color::hex(hex).map_err(|_| format!("Line {}: invalid hex color {}", line, hex))
+}
+
+/// Path and result of the last palette import/export.
+pub struct PaletteFile {
+    pub path: String,
+    pub status: Option<String>,
+}
+
+pub enum PaletteEvent {
+    /// Replaces the palette, cells using entries past its end take the closest new color.
+    Import,
+    Export,
+    /// Writes the palette to [`save_path`].
+    Save,
+    /// Appends a copy of an entry.
+    Add(usize),
+    /// Removes an entry, cells using it take the closest remaining color.
+    Remove(usize),
+    /// Swaps two entries, cells keep their colors.
+    Swap(usize, usize),
+}
+
+/// Everything holding palette indices, which must follow entries being moved or removed.
+#[derive(SystemParam)]
+struct PaletteUsers<'w, 's> {
+    ui_state: ResMut<'w, UiState>,
+    selection: ResMut<'w, Selection>,
+    history: ResMut<'w, History>,
+    cells: Query<'w, 's, &'static mut TileColors>,
+}
+
+/// Colors `fg` and `bg` remapped like those of a cell, see [`Cell::remap`].
+fn remap_colors(fg: usize, bg: usize, f: &dyn Fn(usize) -> usize) -> (usize, usize) {
+    let mut cell = Cell { fg, bg, ..Cell::empty() };
+    cell.remap(f);
+    (cell.fg, cell.bg)
+}
+
+impl<'w, 's> PaletteUsers<'w, 's> {
+    fn remap(&mut self, f: &dyn Fn(usize) -> usize) {
+        for mut colors in self.cells.iter_mut() {
+            let (fg, bg) = remap_colors(colors.fg, colors.bg, f);
+            *colors = TileColors { fg, bg };
+        }
+        (self.ui_state.fg, self.ui_state.bg) = remap_colors(self.ui_state.fg, self.ui_state.bg, f);
+        if let Some(brush) = &mut self.ui_state.brush { brush.remap(f); }
+        if let Some(clipboard) = &mut self.selection.clipboard { clipboard.remap(f); }
+        if let Some(floating) = &mut self.selection.floating { floating.block.remap(f); }
+        self.history.remap(f);
+    }
+}
+
+fn handle_palette_events(
+    mut events: EventReader<PaletteEvent>,
+    mut file: ResMut<PaletteFile>,
+    mut colors: ResMut<Colors>,
+    mut canvas: ResMut<Canvas>,
+    mut users: PaletteUsers,
+) {
+    for event in events.iter() {
+        let result = match *event {
+            PaletteEvent::Import => load(&file.path).map(|palette| {
+                // Entries past the end of the new palette take its closest color
+                let mapping = (0..colors.len())
+                    .map(|k| if k < palette.len() { k } else { palette.nearest(colors.get(k), usize::MAX).unwrap_or(0) })
+                    .collect::<Vec<usize>>();
+                let removed = colors.len().saturating_sub(palette.len());
+                *colors = palette;
+                if removed == 0 { return format!("Imported {} colors", colors.len()); }
+                users.remap(&|k| mapping.get(k).copied().unwrap_or(k));
+                format!("Imported {} colors, {} removed colors replaced by the closest ones", colors.len(), removed)
+            }),
+            PaletteEvent::Export => save(&file.path, &colors).map(|_| format!("Exported {}", file.path)),
+            // Image palettes are saved to a .gpl next to them, which later saves go to
+            PaletteEvent::Save => {
+                let path = save_path(&canvas.palette);
+                save(&path, &colors).map(|_| {
+                    if path == canvas.palette { return format!("Saved {}", path); }
+                    let image = std::mem::replace(&mut canvas.palette, path.clone());
+                    format!("Saved {}, {} is left as is", path, image)
+                })
+            }
+            PaletteEvent::Add(i) | PaletteEvent::Remove(i) | PaletteEvent::Swap(i, _) | PaletteEvent::Swap(_, i)
+                if i >= colors.len() => Err(format!("No color #{}", i)),
+            PaletteEvent::Add(_) if colors.len() >= MAX_COLORS => Err(format!("Palettes have at most {} colors", MAX_COLORS)),
+            PaletteEvent::Add(i) => {
+                let color = colors.get(i);
+                colors.push(color);
+                Ok(format!("Added color #{}", colors.len() - 1))
+            }
+            PaletteEvent::Remove(i) => {
+                let nearest = colors.nearest(colors.get(i), i).unwrap_or(0);
+                colors.remove(i).map(|_| {
+                    let shift = |k: usize| if k > i { k - 1 } else { k };
+                    users.remap(&|k| if k == i { shift(nearest) } else { shift(k) });
+                    format!("Removed color #{}", i)
+                })
+            }
+            PaletteEvent::Swap(i, j) => {
+                colors.swap(i, j);
+                users.remap(&|k| if k == i { j } else if k == j { i } else { k });
+                Ok(format!("Moved color #{} to #{}", i, j))
+            }
+        };
+        file.status = Some(result.unwrap_or_else(|error| error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors() -> Vec<Color> {
+        vec![Color::rgb_u8(0, 0, 0), Color::rgb_u8(255, 128, 7), Color::rgb_u8(18, 52, 86)]
+    }
+
+    #[test]
+    fn text_formats_round_trip() {
+        let palette = Colors::new(colors()).unwrap();
+        for format in [PaletteFormat::Gimp, PaletteFormat::Jasc, PaletteFormat::Hex, PaletteFormat::PaintNet] {
+            assert_eq!(parse(format, &write(format, &palette)), Ok(colors()), "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(PaletteFormat::from_path("a/b.GPL"), Ok(PaletteFormat::Gimp));
+        assert_eq!(PaletteFormat::from_path("b.txt"), Ok(PaletteFormat::PaintNet));
+        assert!(PaletteFormat::from_path("b.ron").is_err());
+        assert_eq!(save_path("assets/palette.png"), "assets/palette.gpl");
+        assert_eq!(save_path("palette.hex"), "palette.hex");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse(PaletteFormat::Gimp, "0 0 0\n").is_err());
+        assert!(parse(PaletteFormat::Gimp, "GIMP Palette\n0 0\n").is_err());
+        assert!(parse(PaletteFormat::Gimp, "GIMP Palette\n0 0 256\n").is_err());
+        assert!(parse(PaletteFormat::Jasc, "JASC-PAL\n0100\n2\n0 0 0\n").is_err());
+        assert!(parse(PaletteFormat::Hex, "12345g\n").is_err());
+        assert!(parse(PaletteFormat::PaintNet, "123456\n").is_err());
+        assert_eq!(parse(PaletteFormat::Hex, "\n"), Err("The palette is empty".to_string()));
+        let many = "000000\n".repeat(MAX_COLORS + 1);
+        assert!(parse(PaletteFormat::Hex, &many).is_err());
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let gpl = "GIMP Palette\nName: test\nColumns: 4\n# comment\n255 128 7 orange\n";
+        assert_eq!(parse(PaletteFormat::Gimp, gpl), Ok(vec![Color::rgb_u8(255, 128, 7)]));
+        assert_eq!(parse(PaletteFormat::PaintNet, "; comment\nFF123456\n"), Ok(vec![Color::rgb_u8(18, 52, 86)]));
+    }
+}
\ No newline at end of file
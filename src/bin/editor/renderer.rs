@@ -0,0 +1,117 @@
+use bevy::prelude::*;
+use bevy_textmode::{CanvasMaterial, Colors, TileColors, TileId, TileMaterial, TilePos};
+use serde::{Deserialize, Serialize};
+use crate::{Grid, Layer};
+use crate::layers::LAYER_Z_STEP;
+
+pub struct RendererPlugin;
+
+impl Plugin for RendererPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system(recolor_cells)
+            // Runs after the tools and layer changes of the frame
+            .add_system_to_stage(CoreStage::PostUpdate, update_canvas_cells)
+            .add_system_to_stage(CoreStage::PostUpdate, update_canvas_palette)
+            .add_system_to_stage(CoreStage::PostUpdate, update_canvas_layers);
+    }
+}
+
+/// How the cells of the canvas are drawn.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Renderer {
+    /// One quad and [`CanvasMaterial`] per layer.
+    Batched,
+    /// One entity with its own mesh and `TileMaterial` per cell.
+    Entities,
+}
+
+impl Renderer {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "batched" => Some(Renderer::Batched),
+            "entities" => Some(Renderer::Entities),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves the palette indices of every cell again when the palette changes.
+fn recolor_cells(
+    colors: Res<Colors>,
+    grid: Res<Grid>,
+    mut materials: ResMut<Assets<TileMaterial>>,
+    cells: Query<(&TileColors, &Handle<TileMaterial>)>,
+) {
+    if !colors.is_changed() { return; }
+    for layer in grid.layers() {
+        recolor_layer(layer, &colors, &mut materials, &cells);
+    }
+}
+
+/// Updates the cell materials of a layer drawn with [`Renderer::Entities`].
+pub fn recolor_layer(
+    layer: &Layer,
+    colors: &Colors,
+    materials: &mut Assets<TileMaterial>,
+    cells: &Query<(&TileColors, &Handle<TileMaterial>)>,
+) {
+    for (tile_colors, handle) in layer.cells().iter().filter_map(|&e| cells.get(e).ok()) {
+        if let Some(material) = materials.get_mut(handle) {
+            material.fg = colors.faded(tile_colors.fg, layer.opacity);
+            material.bg = colors.faded(tile_colors.bg, layer.opacity);
+        }
+    }
+}
+
+/// Cell drawn by the quad of its layer, it has no mesh of its own.
+#[derive(Component)]
+pub struct CanvasCell {
+    pub material: Handle<CanvasMaterial>,
+}
+
+type ChangedCell = Or<(Changed<TileId>, Changed<TileColors>)>;
+
+/// Copies cells written by the tools to the data texture of their layer.
+fn update_canvas_cells(
+    materials: Res<Assets<CanvasMaterial>>,
+    cells: Query<(&TilePos, &TileId, &TileColors, &CanvasCell), ChangedCell>,
+) {
+    for (pos, id, colors, cell) in cells.iter() {
+        if let Some(material) = materials.get(&cell.material) {
+            material.set(pos.x, pos.y, id, colors);
+        }
+    }
+}
+
+fn update_canvas_palette(
+    colors: Res<Colors>,
+    mut materials: ResMut<Assets<CanvasMaterial>>,
+) {
+    if !colors.is_changed() { return; }
+    let palette = colors.iter().copied().collect::<Vec<Color>>();
+    for (_, material) in materials.iter_mut() {
+        material.set_palette(&palette);
+    }
+}
+
+/// Applies the order, visibility and opacity of the layers to their quads.
+fn update_canvas_layers(
+    grid: Res<Grid>,
+    mut materials: ResMut<Assets<CanvasMaterial>>,
+    mut quads: Query<(&mut Transform, &mut Visibility, &Handle<CanvasMaterial>)>,
+) {
+    if !grid.is_changed() { return; }
+    for (i, layer) in grid.layers().iter().enumerate() {
+        let (mut transform, mut visibility, handle) = match layer.quad().and_then(|e| quads.get_mut(e).ok()) {
+            Some(quad) => quad,
+            None => continue,
+        };
+        transform.translation.z = i as f32 * LAYER_Z_STEP;
+        visibility.is_visible = layer.visible;
+        // Changing the material uploads the layer again
+        if matches!(materials.get(handle), Some(material) if material.opacity != layer.opacity) {
+            if let Some(material) = materials.get_mut(handle) { material.opacity = layer.opacity; }
+        }
+    }
+}
\ No newline at end of file
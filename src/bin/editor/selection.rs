@@ -0,0 +1,475 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_textmode::{BasicMesh, Canvas, Cell, Colors, TextModeBundle, TileColors, TileId, TileMaterial, TilePos, Tiles};
+use crate::Grid;
+use crate::cursor::HoveredTile;
+use crate::eyedropper::pick;
+use crate::gui::{Tool, UiState};
+use crate::history::History;
+use crate::paint::{apply_paint, PaintEvent};
+
+/// Opacity of the floating block.
+const FLOATING_ALPHA: f32 = 0.8;
+/// Color of the selection rectangle.
+const MARQUEE_COLOR: Color = Color::rgba(0.3, 0.6, 1.0, 0.3);
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Selection>()
+            .add_event::<SelectionEvent>()
+            .add_system(selection_shortcuts)
+            .add_system(select.after(selection_shortcuts).after(pick).before(apply_paint))
+            .add_system(update_selection_preview.after(select));
+    }
+}
+
+/// A rectangle of cells, indexed by `x + y * width`.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Block {
+    pub width: u32,
+    pub height: u32,
+    pub cells: Vec<Cell>,
+}
+
+impl Block {
+    /// Copies the cells from `min` to `max` (included) of a canvas `canvas_width` cells wide.
+    pub fn copy(cells: &[Cell], canvas_width: u32, min: TilePos, max: TilePos) -> Self {
+        let (width, height) = (max.x - min.x + 1, max.y - min.y + 1);
+        let cells = (min.y..=max.y)
+            .flat_map(|y| (min.x..=max.x).map(move |x| (x + y * canvas_width) as usize))
+            .map(|i| cells[i])
+            .collect();
+        Block { width, height, cells }
+    }
+
+    /// Tiles of a rectangle of the tileset sheet, starting at `index` and going right and down.
+    pub fn from_tileset(tiles: &Tiles, index: usize, width: u32, height: u32, fg: usize, bg: usize) -> Self {
+        let (column, row) = (index % tiles.columns, index / tiles.columns);
+        let width = width.clamp(1, (tiles.columns - column) as u32);
+        let height = height.clamp(1, (tiles.rows - row) as u32);
+        // The first sheet row is the top of the block
+        let cells = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| column + x as usize + (row + (height - 1 - y) as usize) * tiles.columns)
+            .map(|index| Cell { id: TileId { index, flip: false, rotation: 0 }, fg, bg })
+            .collect();
+        Block { width, height, cells }
+    }
+
+    pub fn remap(&mut self, f: &dyn Fn(usize) -> usize) {
+        self.cells.iter_mut().for_each(|cell| cell.remap(f));
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Cell {
+        self.cells[(x + y * self.width) as usize]
+    }
+
+    /// Turns the block 90° counter-clockwise, like [`TileId::rotate`] does for a tile.
+    pub fn rotate(&self) -> Self {
+        let (w, h) = (self.height, self.width);
+        let mut cells = vec![Cell::empty(); self.cells.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut cell = self.get(x, y);
+                cell.id.rotate();
+                cells[((self.height - 1 - y) + x * w) as usize] = cell;
+            }
+        }
+        Block { width: w, height: h, cells }
+    }
+
+    /// Mirrors the block horizontally.
+    pub fn flip(&self) -> Self {
+        let mut cells = vec![Cell::empty(); self.cells.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut cell = self.get(x, y);
+                // Tiles are flipped before being rotated, mirroring the result reverses the rotation
+                cell.id.flip();
+                cell.id.rotation = (4 - cell.id.rotation) % 4;
+                cells[((self.width - 1 - x) + y * self.width) as usize] = cell;
+            }
+        }
+        Block { width: self.width, height: self.height, cells }
+    }
+}
+
+/// Block following the cursor until it's dropped on the canvas.
+pub struct Floating {
+    pub block: Block,
+    /// Canvas position of the bottom left cell, which can be outside of the canvas.
+    pub pos: (i32, i32),
+    /// Cursor position relative to `pos`.
+    grab: (i32, i32),
+    /// Where a moved block was lifted from, to put it back when the move is cancelled.
+    origin: Option<TilePos>,
+}
+
+#[derive(Default)]
+pub struct Selection {
+    /// Bottom left and top right cells of the selected rectangle.
+    pub rect: Option<(TilePos, TilePos)>,
+    pub clipboard: Option<Block>,
+    pub floating: Option<Floating>,
+}
+
+pub enum SelectionEvent {
+    Copy,
+    Cut,
+    Paste,
+    Delete,
+    Rotate,
+    Flip,
+    /// Uses the selected cells as the pencil brush.
+    Brush,
+    /// Drops the floating block, or the selection when there is none.
+    Cancel,
+}
+
+fn selection_shortcuts(
+    mut egui_ctx: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    ui_state: Res<UiState>,
+    mut events: EventWriter<SelectionEvent>,
+) {
+    if egui_ctx.ctx_mut().wants_keyboard_input() { return; }
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if ctrl && keys.just_pressed(KeyCode::V) {
+        events.send(SelectionEvent::Paste);
+    }
+    if ui_state.tool != Tool::Select { return; }
+    if ctrl && keys.just_pressed(KeyCode::C) {
+        events.send(SelectionEvent::Copy);
+    } else if ctrl && keys.just_pressed(KeyCode::X) {
+        events.send(SelectionEvent::Cut);
+    } else if keys.just_pressed(KeyCode::Delete) {
+        events.send(SelectionEvent::Delete);
+    } else if keys.just_pressed(KeyCode::Escape) {
+        events.send(SelectionEvent::Cancel);
+    } else if !ctrl && keys.just_pressed(KeyCode::R) {
+        events.send(SelectionEvent::Rotate);
+    } else if !ctrl && keys.just_pressed(KeyCode::F) {
+        events.send(SelectionEvent::Flip);
+    }
+}
+
+/// Paints `block` with its bottom left cell at `pos`, skipping cells outside of the canvas.
+pub fn stamp(block: &Block, pos: (i32, i32), canvas: &Canvas, events: &mut EventWriter<PaintEvent>) {
+    for y in 0..block.height {
+        for x in 0..block.width {
+            let (cx, cy) = (pos.0 + x as i32, pos.1 + y as i32);
+            if cx < 0 || cy < 0 || cx >= canvas.width as i32 || cy >= canvas.height as i32 { continue; }
+            events.send(PaintEvent { pos: TilePos { x: cx as u32, y: cy as u32 }, cell: block.get(x, y) });
+        }
+    }
+}
+
+fn erase(min: TilePos, max: TilePos, blank: Cell, events: &mut EventWriter<PaintEvent>) {
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            events.send(PaintEvent { pos: TilePos { x, y }, cell: blank });
+        }
+    }
+}
+
+/// Part of the canvas covered by a block placed at `pos`.
+fn placed(block: &Block, pos: (i32, i32), canvas: &Canvas) -> Option<(TilePos, TilePos)> {
+    let (x0, y0) = (pos.0.max(0), pos.1.max(0));
+    let x1 = (pos.0 + block.width as i32 - 1).min(canvas.width as i32 - 1);
+    let y1 = (pos.1 + block.height as i32 - 1).min(canvas.height as i32 - 1);
+    if x0 > x1 || y0 > y1 { return None; }
+    Some((TilePos { x: x0 as u32, y: y0 as u32 }, TilePos { x: x1 as u32, y: y1 as u32 }))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn select(
+    mut egui_ctx: ResMut<EguiContext>,
+    mouse: Res<Input<MouseButton>>,
+    hovered: Res<HoveredTile>,
+    mut ui_state: ResMut<UiState>,
+    canvas: Res<Canvas>,
+    grid: Res<Grid>,
+    cells: Query<(&TileId, &TileColors)>,
+    mut history: ResMut<History>,
+    mut selection: ResMut<Selection>,
+    mut selection_events: EventReader<SelectionEvent>,
+    mut events: EventWriter<PaintEvent>,
+    mut marquee: Local<Option<TilePos>>,
+) {
+    // The canvas may have been replaced
+    if let Some((_, max)) = selection.rect {
+        if max.x >= canvas.width || max.y >= canvas.height { selection.rect = None; }
+    }
+
+    for event in selection_events.iter() {
+        match event {
+            SelectionEvent::Copy | SelectionEvent::Cut => {
+                let (min, max, cells) = match (selection.rect, grid.read(&cells)) {
+                    (Some((min, max)), Some(cells)) => (min, max, cells),
+                    _ => continue,
+                };
+                selection.clipboard = Some(Block::copy(&cells, canvas.width, min, max));
+                if let SelectionEvent::Cut = event {
+                    history.name("Cut");
+                    erase(min, max, grid.blank(), &mut events);
+                }
+            }
+            SelectionEvent::Paste => {
+                let block = match &selection.clipboard {
+                    Some(block) => block.clone(),
+                    None => continue,
+                };
+                cancel(&mut selection, &mut events);
+                // Hold the block by its top left cell
+                let grab = (0, block.height as i32 - 1);
+                let pos = match (hovered.pos, selection.rect) {
+                    (Some(p), _) => (p.x as i32 - grab.0, p.y as i32 - grab.1),
+                    (None, Some((min, _))) => (min.x as i32, min.y as i32),
+                    (None, None) => (0, 0),
+                };
+                selection.floating = Some(Floating { block, pos, grab, origin: None });
+                ui_state.tool = Tool::Select;
+            }
+            SelectionEvent::Brush => {
+                if let (Some((min, max)), Some(cells)) = (selection.rect, grid.read(&cells)) {
+                    ui_state.brush = Some(Block::copy(&cells, canvas.width, min, max));
+                    ui_state.tool = Tool::Pencil;
+                }
+            }
+            SelectionEvent::Delete => {
+                if let Some((min, max)) = selection.rect {
+                    history.name("Delete");
+                    erase(min, max, grid.blank(), &mut events);
+                }
+            }
+            SelectionEvent::Rotate | SelectionEvent::Flip => {
+                if let Some(floating) = &mut selection.floating {
+                    let (w, h) = (floating.block.width as i32, floating.block.height as i32);
+                    if let SelectionEvent::Rotate = event {
+                        floating.block = floating.block.rotate();
+                        // Keep the block around the cursor
+                        floating.grab = (h - 1 - floating.grab.1, floating.grab.0);
+                    } else {
+                        floating.block = floating.block.flip();
+                        floating.grab.0 = w - 1 - floating.grab.0;
+                    }
+                    if let Some(p) = hovered.pos {
+                        floating.pos = (p.x as i32 - floating.grab.0, p.y as i32 - floating.grab.1);
+                    }
+                }
+            }
+            SelectionEvent::Cancel => {
+                if selection.floating.is_some() {
+                    cancel(&mut selection, &mut events);
+                } else {
+                    selection.rect = None;
+                }
+            }
+        }
+    }
+
+    if ui_state.tool != Tool::Select {
+        if selection.rect.is_some() || selection.floating.is_some() {
+            cancel(&mut selection, &mut events);
+            selection.rect = None;
+        }
+        *marquee = None;
+        return;
+    }
+
+    // Only touch the selection when the block moves, the preview is rebuilt on changes
+    if let (Some(floating), Some(p)) = (&selection.floating, hovered.pos) {
+        let pos = (p.x as i32 - floating.grab.0, p.y as i32 - floating.grab.1);
+        if pos != floating.pos { selection.floating.as_mut().unwrap().pos = pos; }
+    }
+
+    if mouse.just_pressed(MouseButton::Right) {
+        if selection.floating.is_some() { cancel(&mut selection, &mut events); } else { selection.rect = None; }
+        *marquee = None;
+        return;
+    }
+
+    if mouse.just_pressed(MouseButton::Left) && !egui_ctx.ctx_mut().is_pointer_over_area() {
+        let pos = match hovered.pos {
+            Some(pos) => pos,
+            None => return,
+        };
+        let inside = matches!(selection.rect,
+            Some((min, max)) if pos.x >= min.x && pos.x <= max.x && pos.y >= min.y && pos.y <= max.y);
+
+        if let Some(floating) = selection.floating.take() {
+            // Drop the pasted block
+            history.name("Paste");
+            stamp(&floating.block, floating.pos, &canvas, &mut events);
+            selection.rect = placed(&floating.block, floating.pos, &canvas);
+        } else if let (true, Some((min, max)), Some(cells)) = (inside, selection.rect, grid.read(&cells)) {
+            // Lift the selection to move it
+            history.name("Move");
+            let block = Block::copy(&cells, canvas.width, min, max);
+            erase(min, max, grid.blank(), &mut events);
+            selection.floating = Some(Floating {
+                block,
+                pos: (min.x as i32, min.y as i32),
+                grab: (pos.x as i32 - min.x as i32, pos.y as i32 - min.y as i32),
+                origin: Some(min),
+            });
+        } else {
+            *marquee = Some(pos);
+            selection.rect = Some((pos, pos));
+        }
+        return;
+    }
+
+    if mouse.pressed(MouseButton::Left) {
+        if let (Some(start), Some(end)) = (*marquee, hovered.pos) {
+            let rect = Some((
+                TilePos { x: start.x.min(end.x), y: start.y.min(end.y) },
+                TilePos { x: start.x.max(end.x), y: start.y.max(end.y) },
+            ));
+            if selection.rect != rect { selection.rect = rect; }
+        }
+    } else {
+        *marquee = None;
+        let moved = matches!(&selection.floating, Some(f) if f.origin.is_some());
+        if moved {
+            let floating = selection.floating.take().unwrap();
+            stamp(&floating.block, floating.pos, &canvas, &mut events);
+            selection.rect = placed(&floating.block, floating.pos, &canvas);
+        }
+    }
+}
+
+/// Drops the floating block, putting a moved block back where it was.
+fn cancel(selection: &mut Selection, events: &mut EventWriter<PaintEvent>) {
+    if let Some(floating) = selection.floating.take() {
+        if let Some(origin) = floating.origin {
+            for y in 0..floating.block.height {
+                for x in 0..floating.block.width {
+                    let pos = TilePos { x: origin.x + x, y: origin.y + y };
+                    events.send(PaintEvent { pos, cell: floating.block.get(x, y) });
+                }
+            }
+        }
+    }
+}
+
+/// Marker of the selection rectangle and floating block entities.
+#[derive(Component)]
+struct SelectionPreview;
+
+#[allow(clippy::too_many_arguments)]
+fn update_selection_preview(
+    mut commands: Commands,
+    selection: Res<Selection>,
+    tiles: Res<Tiles>,
+    colors: Res<Colors>,
+    meshes: Res<BasicMesh>,
+    canvas: Res<Canvas>,
+    mut materials: ResMut<Assets<TileMaterial>>,
+    previews: Query<Entity, With<SelectionPreview>>,
+) {
+    if !selection.is_changed() && !colors.is_changed() && !tiles.is_changed() { return; }
+    previews.iter().for_each(|e| commands.entity(e).despawn());
+
+    let tile = canvas.tile_size as f32;
+    if let (Some((min, max)), None) = (selection.rect, &selection.floating) {
+        let size = Vec2::new((max.x - min.x + 1) as f32, (max.y - min.y + 1) as f32) * tile;
+        let center = Vec2::new((min.x + max.x) as f32, (min.y + max.y) as f32) * tile / 2. + canvas.offset;
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite { color: MARQUEE_COLOR, custom_size: Some(size), ..Default::default() },
+                transform: Transform::from_translation(center.extend(2.)),
+                ..Default::default()
+            })
+            .insert(SelectionPreview);
+    }
+
+    if let Some(floating) = &selection.floating {
+        for y in 0..floating.block.height {
+            for x in 0..floating.block.width {
+                let (cx, cy) = (floating.pos.0 + x as i32, floating.pos.1 + y as i32);
+                if cx < 0 || cy < 0 || cx >= canvas.width as i32 || cy >= canvas.height as i32 { continue; }
+                let cell = floating.block.get(x, y);
+                // The clipboard may come from another tileset
+                if !tiles.contains(&cell.id) { continue; }
+                let mut fg = colors.get(cell.fg);
+                let mut bg = colors.get(cell.bg);
+                fg.set_a(FLOATING_ALPHA);
+                bg.set_a(FLOATING_ALPHA);
+                let mut bundle = TextModeBundle::new(
+                    &tiles, &mut materials,
+                    &cell.id,
+                    cx as u32, cy as u32,
+                    bg, fg,
+                    meshes.tile.clone(), &canvas
+                );
+                bundle.transform.translation.z = 1.;
+                commands.spawn_bundle(bundle).insert(SelectionPreview);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(index: usize) -> Cell {
+        Cell { id: TileId { index, ..TileId::new() }, fg: 0, bg: 0 }
+    }
+
+    /// 3×2 block whose cells are glyphs 0 to 5.
+    fn block() -> Block {
+        Block { width: 3, height: 2, cells: (0..6).map(cell).collect() }
+    }
+
+    /// Size of the block, then the glyph, flip and rotation of each cell.
+    fn layout(block: &Block) -> (u32, u32, Vec<(usize, bool, u8)>) {
+        let ids = block.cells.iter().map(|cell| (cell.id.index, cell.id.flip, cell.id.rotation)).collect();
+        (block.width, block.height, ids)
+    }
+
+    fn glyphs(block: &Block) -> Vec<usize> {
+        block.cells.iter().map(|cell| cell.id.index).collect()
+    }
+
+    #[test]
+    fn rotate_turns_counterclockwise() {
+        let rotated = block().rotate();
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        // The top right cell (2, 1) ends at the top left, y being up
+        assert_eq!(rotated.get(0, 2).id.index, 5);
+        assert_eq!(glyphs(&rotated), vec![3, 0, 4, 1, 5, 2]);
+        assert!(rotated.cells.iter().all(|cell| cell.id.rotation == 1));
+        assert_eq!(layout(&block().rotate().rotate().rotate().rotate()), layout(&block()));
+    }
+
+    #[test]
+    fn flip_mirrors_horizontally() {
+        let flipped = block().flip();
+        assert_eq!(glyphs(&flipped), vec![2, 1, 0, 5, 4, 3]);
+        assert!(flipped.cells.iter().all(|cell| cell.id.flip));
+        assert_eq!(layout(&flipped.flip()), layout(&block()));
+    }
+
+    #[test]
+    fn flip_undoes_the_rotation_of_tiles() {
+        let mut block = block();
+        block.cells[0].id.rotation = 1;
+        let flipped = block.flip();
+        assert_eq!(flipped.get(2, 0).id.rotation, 3);
+        // A half turn and a mirror commute, tile transforms included
+        assert_eq!(layout(&flipped.rotate().rotate()), layout(&block.rotate().rotate().flip()));
+    }
+
+    #[test]
+    fn copy_reads_the_rectangle() {
+        let cells = (0..12).map(cell).collect::<Vec<Cell>>();
+        let copied = Block::copy(&cells, 4, TilePos { x: 1, y: 1 }, TilePos { x: 2, y: 2 });
+        assert_eq!((copied.width, copied.height), (2, 2));
+        assert_eq!(glyphs(&copied), vec![5, 6, 9, 10]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,213 @@
+use std::collections::HashSet;
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_textmode::{BasicMesh, Canvas, Cell, Colors, TextModeBundle, TileMaterial, TilePos, Tiles};
+use crate::cursor::HoveredTile;
+use crate::eyedropper::pick;
+use crate::gui::{Tool, UiState};
+use crate::history::History;
+use crate::paint::{apply_paint, PaintEvent};
+
+/// Opacity of the shape preview.
+const PREVIEW_ALPHA: f32 = 0.6;
+
+pub struct ShapePlugin;
+
+impl Plugin for ShapePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(draw_shape.after(pick).before(apply_paint));
+    }
+}
+
+/// Marker of the cells previewing the shape being drawn.
+#[derive(Component)]
+struct ShapePreview;
+
+#[derive(Default)]
+struct ShapeDrag {
+    start: Option<TilePos>,
+    end: Option<TilePos>,
+    preview: Vec<Entity>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_shape(
+    mut commands: Commands,
+    mut egui_ctx: ResMut<EguiContext>,
+    mouse: Res<Input<MouseButton>>,
+    hovered: Res<HoveredTile>,
+    ui_state: Res<UiState>,
+    tiles: Res<Tiles>,
+    colors: Res<Colors>,
+    meshes: Res<BasicMesh>,
+    canvas: Res<Canvas>,
+    mut materials: ResMut<Assets<TileMaterial>>,
+    mut history: ResMut<History>,
+    mut events: EventWriter<PaintEvent>,
+    mut drag: Local<ShapeDrag>,
+) {
+    let tool = ui_state.tool;
+    let is_shape = matches!(tool, Tool::Line | Tool::Rectangle | Tool::Ellipse);
+
+    if is_shape && mouse.just_pressed(MouseButton::Left) && !egui_ctx.ctx_mut().is_pointer_over_area() {
+        drag.start = hovered.pos;
+        drag.end = None;
+    }
+    let start = match drag.start {
+        Some(start) if is_shape && !mouse.pressed(MouseButton::Right) => start,
+        _ => {
+            // Cancelled with a right click or by switching tool
+            drag.preview.drain(..).for_each(|e| commands.entity(e).despawn());
+            drag.start = None;
+            return;
+        }
+    };
+
+    let end = hovered.pos.or(drag.end).unwrap_or(start);
+    let cells = shape(tool, start, end, ui_state.shape_filled);
+
+    if mouse.just_released(MouseButton::Left) || !mouse.pressed(MouseButton::Left) {
+        let cell = Cell { id: ui_state.tile_id, fg: ui_state.fg, bg: ui_state.bg };
+        history.name(match tool {
+            Tool::Line => "Line",
+            Tool::Rectangle => "Rectangle",
+            _ => "Ellipse",
+        });
+        cells.into_iter().for_each(|pos| events.send(PaintEvent { pos, cell }));
+        drag.preview.drain(..).for_each(|e| commands.entity(e).despawn());
+        drag.start = None;
+        return;
+    }
+
+    if drag.end == Some(end) && !drag.preview.is_empty() { return; }
+    drag.end = Some(end);
+    drag.preview.drain(..).for_each(|e| commands.entity(e).despawn());
+
+    let mut fg = colors.get(ui_state.fg);
+    let mut bg = colors.get(ui_state.bg);
+    fg.set_a(PREVIEW_ALPHA);
+    bg.set_a(PREVIEW_ALPHA);
+    for pos in cells {
+        let mut bundle = TextModeBundle::new(
+            &tiles, &mut materials,
+            &ui_state.tile_id,
+            pos.x, pos.y,
+            bg, fg,
+            meshes.tile.clone(), &canvas
+        );
+        bundle.transform.translation.z = 1.;
+        drag.preview.push(commands.spawn_bundle(bundle).insert(ShapePreview).id());
+    }
+}
+
+/// Cells covered by the shape drawn by `tool` from `a` to `b`.
+pub fn shape(tool: Tool, a: TilePos, b: TilePos, filled: bool) -> Vec<TilePos> {
+    match tool {
+        Tool::Line => line(a, b),
+        Tool::Rectangle => rectangle(a, b, filled),
+        Tool::Ellipse => ellipse(a, b, filled),
+        _ => vec![],
+    }
+}
+
+/// Bresenham line from `a` to `b`.
+pub fn line(a: TilePos, b: TilePos) -> Vec<TilePos> {
+    let (mut x, mut y) = (a.x as i32, a.y as i32);
+    let (x1, y1) = (b.x as i32, b.y as i32);
+    let (dx, dy) = ((x1 - x).abs(), -(y1 - y).abs());
+    let (sx, sy) = (if x < x1 { 1 } else { -1 }, if y < y1 { 1 } else { -1 });
+    let mut err = dx + dy;
+    let mut cells = vec![];
+    loop {
+        cells.push(TilePos { x: x as u32, y: y as u32 });
+        if x == x1 && y == y1 { break; }
+        let e2 = 2 * err;
+        if e2 >= dy { err += dy; x += sx; }
+        if e2 <= dx { err += dx; y += sy; }
+    }
+    cells
+}
+
+pub fn rectangle(a: TilePos, b: TilePos, filled: bool) -> Vec<TilePos> {
+    let (x0, x1) = (a.x.min(b.x), a.x.max(b.x));
+    let (y0, y1) = (a.y.min(b.y), a.y.max(b.y));
+    let mut cells = vec![];
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            if filled || x == x0 || x == x1 || y == y0 || y == y1 {
+                cells.push(TilePos { x, y });
+            }
+        }
+    }
+    cells
+}
+
+/// Ellipse inscribed in the rectangle from `a` to `b`.
+pub fn ellipse(a: TilePos, b: TilePos, filled: bool) -> Vec<TilePos> {
+    let (x0, x1) = (a.x.min(b.x) as i32, a.x.max(b.x) as i32);
+    let (y0, y1) = (a.y.min(b.y) as i32, a.y.max(b.y) as i32);
+    let (cx, cy) = ((x0 + x1) as f32 / 2., (y0 + y1) as f32 / 2.);
+    let (rx, ry) = ((x1 - x0 + 1) as f32 / 2., (y1 - y0 + 1) as f32 / 2.);
+
+    let inside = |x: i32, y: i32| {
+        let (dx, dy) = ((x as f32 - cx) / rx, (y as f32 - cy) / ry);
+        dx * dx + dy * dy <= 1.
+    };
+    let area: HashSet<(i32, i32)> = (y0..=y1)
+        .flat_map(|y| (x0..=x1).map(move |x| (x, y)))
+        .filter(|&(x, y)| inside(x, y))
+        .collect();
+
+    let mut cells = area.iter()
+        .filter(|&&(x, y)| filled || [(1, 0), (-1, 0), (0, 1), (0, -1)].iter()
+            .any(|(dx, dy)| !area.contains(&(x + dx, y + dy))))
+        .map(|&(x, y)| TilePos { x: x as u32, y: y as u32 })
+        .collect::<Vec<TilePos>>();
+    cells.sort_by_key(|p| (p.y, p.x));
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: u32, y: u32) -> TilePos {
+        TilePos { x, y }
+    }
+
+    fn coords(cells: &[TilePos]) -> Vec<(u32, u32)> {
+        cells.iter().map(|p| (p.x, p.y)).collect()
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(coords(&line(pos(0, 0), pos(3, 0))), vec![(0, 0), (1, 0), (2, 0), (3, 0)]);
+        assert_eq!(coords(&line(pos(2, 2), pos(0, 0))), vec![(2, 2), (1, 1), (0, 0)]);
+        assert_eq!(coords(&line(pos(1, 1), pos(1, 1))), vec![(1, 1)]);
+        // One cell per column when the line is wider than tall
+        let cells = coords(&line(pos(0, 0), pos(5, 2)));
+        assert_eq!(cells.len(), 6);
+        assert_eq!((cells[0], cells[5]), ((0, 0), (5, 2)));
+    }
+
+    #[test]
+    fn rectangles() {
+        assert_eq!(rectangle(pos(3, 2), pos(0, 0), false).len(), 10);
+        assert_eq!(rectangle(pos(0, 0), pos(3, 2), true).len(), 12);
+        assert_eq!(coords(&rectangle(pos(1, 1), pos(1, 1), false)), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn ellipses() {
+        let outline = coords(&ellipse(pos(0, 0), pos(6, 4), false));
+        let area = coords(&ellipse(pos(6, 4), pos(0, 0), true));
+        assert!(outline.iter().all(|cell| area.contains(cell)));
+        assert!(area.contains(&(3, 2)) && !outline.contains(&(3, 2)));
+        // Touches each side of the rectangle but not its corners
+        for cell in [(0, 2), (6, 2), (3, 0), (3, 4)] { assert!(outline.contains(&cell)); }
+        for cell in [(0, 0), (6, 0), (0, 4), (6, 4)] { assert!(!area.contains(&cell)); }
+        // Symmetric around the center
+        assert!(area.iter().all(|&(x, y)| area.contains(&(6 - x, y)) && area.contains(&(x, 4 - y))));
+        assert_eq!(coords(&ellipse(pos(2, 2), pos(2, 2), false)), vec![(2, 2)]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use bevy::input::InputSystem;
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use bevy_textmode::{Canvas, Cell, TileId, TilePos};
+use crate::Grid;
+use crate::camera::pan;
+use crate::cursor::HoveredTile;
+use crate::gui::{Tool, UiState};
+use crate::history::History;
+use crate::paint::PaintEvent;
+
+/// Above the overlay, below the mouse cursor.
+const CARET_Z: f32 = 6.;
+
+/// CP437 glyphs by code, the first one is unused.
+const CP437: [&str; 16] = [
+    "\0☺☻♥♦♣♠•◘○◙♂♀♪♫☼", "►◄↕‼¶§▬↨↑↓→←∟↔▲▼",
+    " !\"#$%&'()*+,-./", "0123456789:;<=>?",
+    "@ABCDEFGHIJKLMNO", "PQRSTUVWXYZ[\\]^_",
+    "`abcdefghijklmno", "pqrstuvwxyz{|}~⌂",
+    "ÇüéâäàåçêëèïîìÄÅ", "ÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»", "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧", "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩", "≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}",
+];
+
+pub struct TextPlugin;
+
+impl Plugin for TextPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(CharMap {
+                path: String::new(),
+                preset: CharPreset::Cp437,
+                chars: BTreeMap::new(),
+                status: None,
+            })
+            .init_resource::<TextCaret>()
+            .add_event::<SaveCharMap>()
+            .add_startup_system(setup)
+            .add_system(load_char_map)
+            .add_system(save_char_map)
+            // Runs before the shortcuts so typed letters don't trigger them
+            .add_system_to_stage(CoreStage::PreUpdate, type_text.after(InputSystem).before(pan))
+            .add_system(draw_caret);
+    }
+}
+
+/// Glyph layout of the tileset for characters without a custom glyph.
+#[derive(Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum CharPreset {
+    /// Code page 437, used by most 16×16 glyph sheets.
+    Cp437,
+    /// Printable ASCII characters at their code, other characters are ignored.
+    Ascii,
+}
+
+impl CharPreset {
+    fn glyph(&self, c: char) -> Option<usize> {
+        match self {
+            CharPreset::Cp437 => CP437.iter()
+                .flat_map(|row| row.chars())
+                .skip(1)
+                .position(|glyph| glyph == c)
+                .map(|i| i + 1),
+            CharPreset::Ascii => if (' '..='~').contains(&c) { Some(c as usize) } else { None },
+        }
+    }
+}
+
+/// Glyph typed for each character by the text tool.
+///
+/// Read from `<tileset>.chars.ron`, CP437 is used when it doesn't exist.
+pub struct CharMap {
+    pub path: String,
+    pub preset: CharPreset,
+    /// Glyphs overriding the preset.
+    pub chars: BTreeMap<char, usize>,
+    pub status: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CharMapFile {
+    preset: CharPreset,
+    #[serde(default)]
+    chars: BTreeMap<char, usize>,
+}
+
+impl CharMap {
+    pub fn glyph(&self, c: char) -> Option<usize> {
+        self.chars.get(&c).copied().or_else(|| self.preset.glyph(c))
+    }
+}
+
+/// Writes the current [`CharMap`] next to the tileset.
+pub struct SaveCharMap;
+
+fn char_map_path(tileset: &str) -> String {
+    Path::new(tileset).with_extension("chars.ron").to_string_lossy().to_string()
+}
+
+fn load_char_map(
+    canvas: Res<Canvas>,
+    mut char_map: ResMut<CharMap>,
+) {
+    let path = char_map_path(&canvas.tileset);
+    if char_map.path == path { return; }
+
+    let file = fs::read_to_string(&path).ok().map(|text| ron::from_str::<CharMapFile>(&text));
+    let (preset, chars, status) = match file {
+        Some(Ok(file)) => (file.preset, file.chars, None),
+        Some(Err(e)) => (CharPreset::Cp437, BTreeMap::new(), Some(format!("Invalid {}: {}", path, e))),
+        None => (CharPreset::Cp437, BTreeMap::new(), None),
+    };
+    *char_map = CharMap { path, preset, chars, status };
+}
+
+fn save_char_map(
+    mut events: EventReader<SaveCharMap>,
+    mut char_map: ResMut<CharMap>,
+) {
+    for _ in events.iter() {
+        let file = CharMapFile { preset: char_map.preset, chars: char_map.chars.clone() };
+        let result = ron::ser::to_string_pretty(&file, PrettyConfig::new().depth_limit(2))
+            .map_err(|e| e.to_string())
+            .and_then(|text| fs::write(&char_map.path, text).map_err(|e| e.to_string()));
+        char_map.status = Some(match result {
+            Ok(_) => format!("Saved {}", char_map.path),
+            Err(e) => format!("Couldn't write {}: {}", char_map.path, e),
+        });
+    }
+}
+
+/// Cell where the next character is typed.
+#[derive(Default)]
+pub struct TextCaret {
+    pub pos: Option<TilePos>,
+    /// Column Enter goes back to.
+    pub line_start: u32,
+}
+
+/// Types at the caret: characters move it right, Backspace erases to the left, Enter goes to
+/// the next row and the arrows move it. Escape or a click outside of the canvas stops typing.
+#[allow(clippy::too_many_arguments)]
+fn type_text(
+    mut egui_ctx: ResMut<EguiContext>,
+    mouse: Res<Input<MouseButton>>,
+    mut keys: ResMut<Input<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    hovered: Res<HoveredTile>,
+    ui_state: Res<UiState>,
+    char_map: Res<CharMap>,
+    canvas: Res<Canvas>,
+    grid: Res<Grid>,
+    mut caret: ResMut<TextCaret>,
+    mut history: ResMut<History>,
+    mut events: EventWriter<PaintEvent>,
+) {
+    if ui_state.tool != Tool::Text {
+        if caret.pos.is_some() { caret.pos = None; }
+        characters.iter().for_each(drop);
+        return;
+    }
+    // Alt+click picks with the eyedropper, which runs later
+    let alt = keys.any_pressed([KeyCode::LAlt, KeyCode::RAlt]);
+    if mouse.just_pressed(MouseButton::Left) && !alt && !egui_ctx.ctx_mut().is_pointer_over_area() {
+        caret.pos = hovered.pos;
+        caret.line_start = hovered.pos.map_or(0, |pos| pos.x);
+    }
+    let mut pos = match caret.pos {
+        Some(pos) if !egui_ctx.ctx_mut().wants_keyboard_input() => pos,
+        _ => {
+            characters.iter().for_each(drop);
+            return;
+        }
+    };
+
+    // Ctrl shortcuts like undo still work while typing
+    let ctrl = keys.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if ctrl {
+        characters.iter().for_each(drop);
+        return;
+    }
+    let pressed = keys.get_just_pressed().copied().collect::<Vec<KeyCode>>();
+    let (width, height) = (canvas.width, canvas.height);
+    let next_line = |pos: TilePos, line_start: u32| TilePos { x: line_start, y: pos.y.saturating_sub(1) };
+
+    for key in &pressed {
+        match key {
+            KeyCode::Escape => {
+                caret.pos = None;
+                return;
+            }
+            KeyCode::Back if pos.x > 0 => {
+                pos.x -= 1;
+                history.name("Text");
+                events.send(PaintEvent { pos, cell: grid.blank() });
+            }
+            KeyCode::Return | KeyCode::NumpadEnter => pos = next_line(pos, caret.line_start),
+            KeyCode::Left => pos.x = pos.x.saturating_sub(1),
+            KeyCode::Right => pos.x = (pos.x + 1).min(width - 1),
+            KeyCode::Up => pos.y = (pos.y + 1).min(height - 1),
+            KeyCode::Down => pos.y = pos.y.saturating_sub(1),
+            _ => {}
+        }
+        if matches!(key, KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down) { caret.line_start = pos.x; }
+    }
+
+    for event in characters.iter() {
+        if event.char.is_control() { continue; }
+        let index = match char_map.glyph(event.char) {
+            Some(index) => index,
+            None => continue,
+        };
+        history.name("Text");
+        events.send(PaintEvent {
+            pos,
+            cell: Cell { id: TileId { index, flip: false, rotation: 0 }, fg: ui_state.fg, bg: ui_state.bg },
+        });
+        // Wrap at the right edge of the canvas
+        pos = if pos.x + 1 < width { TilePos { x: pos.x + 1, y: pos.y } } else { next_line(pos, caret.line_start) };
+    }
+
+    if caret.pos != Some(pos) { caret.pos = Some(pos); }
+    // Hide the typed keys from the other shortcuts
+    pressed.into_iter().for_each(|key| keys.reset(key));
+    keys.reset(KeyCode::Space);
+}
+
+#[derive(Component)]
+struct CaretSprite;
+
+fn setup(mut commands: Commands) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite { color: Color::rgba(1., 1., 1., 0.8), ..Default::default() },
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(CaretSprite);
+}
+
+/// Underlines the cell of the caret.
+fn draw_caret(
+    caret: Res<TextCaret>,
+    canvas: Res<Canvas>,
+    mut sprite: Query<(&mut Transform, &mut Visibility), With<CaretSprite>>,
+) {
+    if !caret.is_changed() && !canvas.is_changed() { return; }
+    let (mut transform, mut visibility) = sprite.single_mut();
+    visibility.is_visible = caret.pos.is_some();
+    if let Some(pos) = caret.pos {
+        let tile = canvas.tile_size as f32;
+        let center = Vec2::new(pos.x as f32, pos.y as f32) * tile + canvas.offset - Vec2::new(0., tile * 7. / 16.);
+        transform.translation = center.extend(CARET_Z);
+        transform.scale = Vec3::new(tile, tile / 8., 1.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cp437_rows_have_16_glyphs() {
+        assert!(CP437.iter().all(|row| row.chars().count() == 16));
+    }
+
+    #[test]
+    fn cp437_glyphs() {
+        let glyph = |c| CharPreset::Cp437.glyph(c);
+        assert_eq!(glyph('☺'), Some(1));
+        assert_eq!(glyph(' '), Some(32));
+        assert_eq!(glyph('A'), Some(65));
+        assert_eq!(glyph('~'), Some(126));
+        assert_eq!(glyph('é'), Some(130));
+        assert_eq!(glyph('█'), Some(219));
+        assert_eq!(glyph('\u{a0}'), Some(255));
+        assert_eq!(glyph('\0'), None);
+        assert_eq!(glyph('€'), None);
+    }
+
+    #[test]
+    fn ascii_glyphs() {
+        assert_eq!(CharPreset::Ascii.glyph('a'), Some(97));
+        assert_eq!(CharPreset::Ascii.glyph('\n'), None);
+        assert_eq!(CharPreset::Ascii.glyph('é'), None);
+    }
+
+    #[test]
+    fn custom_glyphs_override_the_preset() {
+        let map = CharMap {
+            path: String::new(),
+            preset: CharPreset::Ascii,
+            chars: BTreeMap::from([('a', 4), ('€', 200)]),
+            status: None,
+        };
+        assert_eq!(map.glyph('a'), Some(4));
+        assert_eq!(map.glyph('€'), Some(200));
+        assert_eq!(map.glyph('b'), Some(98));
+        assert_eq!(char_map_path("assets/font.png"), "assets/font.chars.ron");
+    }
+}
\ No newline at end of file
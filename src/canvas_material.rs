@@ -0,0 +1,318 @@
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use bevy::ecs::system::lifetimeless::SRes;
+use bevy::ecs::system::SystemParamItem;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::render_asset::{PrepareAssetError, RenderAsset, RenderAssets};
+use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, BufferInitDescriptor, BufferSize, BufferUsages, Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, ShaderStages, Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension};
+use bevy::render::render_resource::std140::{AsStd140, Std140};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::sprite::{Material2d, Material2dPipeline};
+use crate::{TileId, TRANSPARENT};
+use crate::tiles::TileColors;
+
+/// Palette index of transparent cells in [`CanvasMaterial::cells`].
+const TRANSPARENT_INDEX: u16 = u16::MAX;
+/// Palette index of cells whose index doesn't fit, drawn with [`MISSING`](crate::MISSING).
+const MISSING_INDEX: u16 = u16::MAX - 1;
+
+/// Cells of a [`CanvasMaterial`], shared with its prepared version in the render world.
+#[derive(Debug)]
+struct CanvasCells {
+    /// Glyph index, flip and rotation, fg and bg of each cell, indexed by `x + y * width`.
+    data: Vec<[u16; 4]>,
+    /// Rows written since the cells were last uploaded.
+    dirty: Option<(u32, u32)>,
+}
+
+/// A whole layer drawn by a single quad: the shader looks up each cell in a data texture and
+/// its glyph in the tileset atlas.
+///
+/// Cells are written with [`CanvasMaterial::set`] through `Assets::get`, and only the rows that
+/// changed are uploaded the next frame. Changing the other fields through `Assets::get_mut`
+/// prepares the whole material again.
+#[derive(Debug, Component, TypeUuid)]
+#[uuid = "3b0f5d2e-8c61-4d7a-9a43-6f1e2c9b7d05"]
+pub struct CanvasMaterial {
+    pub atlas: Handle<Image>,
+    pub tile_size: u32,
+    pub width: u32,
+    pub height: u32,
+    cells: Arc<Mutex<CanvasCells>>,
+    /// Palette in linear colors.
+    palette: Vec<[f32; 4]>,
+    pub opacity: f32,
+}
+
+impl CanvasMaterial {
+    pub fn new(atlas: Handle<Image>, tile_size: u32, width: u32, height: u32, palette: &[Color], opacity: f32) -> Self {
+        let mut material = CanvasMaterial {
+            atlas,
+            tile_size,
+            width,
+            height,
+            cells: Arc::new(Mutex::new(CanvasCells { data: vec![[0; 4]; (width * height) as usize], dirty: None })),
+            palette: vec![],
+            opacity,
+        };
+        material.set_palette(palette);
+        material
+    }
+
+    /// Writes the cell at `(x, y)`, y up. Glyphs past [`u16::MAX`] don't fit in the cells texture
+    /// and are drawn as glyph 0.
+    pub fn set(&self, x: u32, y: u32, id: &TileId, colors: &TileColors) {
+        let color = |i: usize| match i {
+            TRANSPARENT => TRANSPARENT_INDEX,
+            i => u16::try_from(i).unwrap_or(MISSING_INDEX),
+        };
+        if x >= self.width { return; }
+        let mut cells = self.cells.lock().unwrap();
+        if let Some(cell) = cells.data.get_mut((x + y * self.width) as usize) {
+            *cell = [
+                u16::try_from(id.index).unwrap_or(0),
+                id.flip as u16 | (id.rotation as u16 % 4) << 1,
+                color(colors.fg),
+                color(colors.bg),
+            ];
+            cells.dirty = Some(match cells.dirty {
+                Some((start, end)) => (start.min(y), end.max(y + 1)),
+                None => (y, y + 1),
+            });
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: &[Color]) {
+        self.palette = palette.iter().map(|c| c.as_linear_rgba_f32()).collect();
+    }
+}
+
+#[derive(Clone)]
+pub struct GpuCanvasMaterial {
+    bind_group: BindGroup,
+    width: u32,
+    cells: Arc<Mutex<CanvasCells>>,
+    cells_texture: Texture,
+}
+
+impl RenderAsset for CanvasMaterial {
+    type ExtractedAsset = CanvasMaterial;
+    type PreparedAsset = GpuCanvasMaterial;
+    type Param = (
+        SRes<RenderDevice>,
+        SRes<RenderQueue>,
+        SRes<RenderAssets<Image>>,
+        SRes<Material2dPipeline<Self>>,
+    );
+    fn extract_asset(&self) -> Self::ExtractedAsset {
+        // The cells aren't copied, they are shared with the render world
+        CanvasMaterial {
+            atlas: self.atlas.clone(),
+            tile_size: self.tile_size,
+            width: self.width,
+            height: self.height,
+            cells: self.cells.clone(),
+            palette: self.palette.clone(),
+            opacity: self.opacity,
+        }
+    }
+
+    fn prepare_asset(
+        extracted_asset: Self::ExtractedAsset,
+        (render_device, render_queue, gpu_images, material_pipeline): &mut SystemParamItem<Self::Param>,
+    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+        let atlas = match gpu_images.get(&extracted_asset.atlas) {
+            Some(gpu_image) => gpu_image,
+            // if the image isn't loaded yet, try next frame
+            None => return Err(PrepareAssetError::RetryNextUpdate(extracted_asset)),
+        };
+
+        let data_texture = |width: u32, height: u32, format: TextureFormat, data: &[u8]| render_device
+            .create_texture_with_data(render_queue, &TextureDescriptor {
+                label: None,
+                size: Extent3d { width, height, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            }, data);
+
+        // Every row is uploaded, later writes only upload the rows they change
+        let cells_texture = {
+            let mut cells = extracted_asset.cells.lock().unwrap();
+            cells.dirty = None;
+            data_texture(extracted_asset.width, extracted_asset.height, TextureFormat::Rgba16Uint, &cell_bytes(&cells.data))
+        };
+        let cells = cells_texture.create_view(&TextureViewDescriptor::default());
+        // The texture can't be empty, the shader checks indices against the palette size
+        let mut palette = extracted_asset.palette.clone();
+        if palette.is_empty() { palette.push([0.; 4]); }
+        let palette = data_texture(
+            palette.len() as u32,
+            1,
+            TextureFormat::Rgba32Float,
+            &palette.iter().flatten().flat_map(|v| v.to_le_bytes()).collect::<Vec<u8>>(),
+        ).create_view(&TextureViewDescriptor::default());
+
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&atlas.texture_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(&cells),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(&palette),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                        contents: Vec4::new(extracted_asset.opacity, extracted_asset.tile_size as f32, 0., 0.).as_std140().as_bytes(),
+                        label: None,
+                        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                    }).as_entire_binding()
+                },
+            ],
+            label: None,
+            layout: &material_pipeline.material2d_layout,
+        });
+
+        Ok(GpuCanvasMaterial {
+            bind_group,
+            width: extracted_asset.width,
+            cells: extracted_asset.cells,
+            cells_texture,
+        })
+    }
+}
+
+fn cell_bytes(cells: &[[u16; 4]]) -> Vec<u8> {
+    cells.iter().flatten().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// Uploads the rows of cells written since the last frame, runs after the materials are prepared.
+pub(crate) fn write_canvas_cells(
+    render_queue: Res<RenderQueue>,
+    materials: Res<RenderAssets<CanvasMaterial>>,
+) {
+    for material in materials.values() {
+        let mut cells = material.cells.lock().unwrap();
+        let (start, end) = match cells.dirty.take() {
+            Some(rows) => rows,
+            None => continue,
+        };
+        let width = material.width;
+        render_queue.write_texture(
+            ImageCopyTexture {
+                texture: &material.cells_texture,
+                mip_level: 0,
+                origin: Origin3d { x: 0, y: start, z: 0 },
+                aspect: TextureAspect::All,
+            },
+            &cell_bytes(&cells.data[(start * width) as usize..(end * width) as usize]),
+            ImageDataLayout {
+                offset: 0,
+                // Rgba16Uint texels
+                bytes_per_row: NonZeroU32::new(width * 8),
+                rows_per_image: None,
+            },
+            Extent3d { width, height: end - start, depth_or_array_layers: 1 },
+        );
+    }
+}
+
+impl Material2d for CanvasMaterial {
+    fn bind_group(render_asset: &<Self as RenderAsset>::PreparedAsset) -> &BindGroup {
+        &render_asset.bind_group
+    }
+
+    fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+        let texture = |binding: u32, sample_type: TextureSampleType| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                sample_type,
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                texture(0, TextureSampleType::Float { filterable: true }),
+                texture(1, TextureSampleType::Uint),
+                texture(2, TextureSampleType::Float { filterable: false }),
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(Vec4::std140_size_static() as u64),
+                    },
+                    count: None,
+                }
+            ],
+            label: None,
+        })
+    }
+
+    fn fragment_shader(asset_server: &AssetServer) -> Option<Handle<Shader>> {
+        Some(asset_server.load("canvas.wgsl"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> CanvasMaterial {
+        CanvasMaterial::new(Handle::default(), 8, 3, 4, &[Color::BLACK, Color::WHITE], 1.)
+    }
+
+    fn cell(material: &CanvasMaterial, x: u32, y: u32) -> [u16; 4] {
+        material.cells.lock().unwrap().data[(x + y * material.width) as usize]
+    }
+
+    fn dirty(material: &CanvasMaterial) -> Option<(u32, u32)> {
+        material.cells.lock().unwrap().dirty
+    }
+
+    #[test]
+    fn set_encodes_glyphs_and_colors() {
+        let material = material();
+        material.set(2, 1, &TileId { index: 300, flip: true, rotation: 3 }, &TileColors { fg: 1, bg: TRANSPARENT });
+        assert_eq!(cell(&material, 2, 1), [300, 7, 1, TRANSPARENT_INDEX]);
+
+        // Glyphs and colors don't share the sentinels
+        material.set(0, 0, &TileId { index: 70_000, flip: false, rotation: 5 }, &TileColors { fg: 70_000, bg: 65_535 });
+        assert_eq!(cell(&material, 0, 0), [0, 2, MISSING_INDEX, TRANSPARENT_INDEX]);
+        material.set(0, 0, &TileId { index: 65_535, ..TileId::new() }, &TileColors { fg: 0, bg: 65_534 });
+        assert_eq!(cell(&material, 0, 0), [65_535, 0, 0, MISSING_INDEX]);
+    }
+
+    #[test]
+    fn set_tracks_the_written_rows() {
+        let material = material();
+        assert_eq!(dirty(&material), None);
+        let colors = TileColors { fg: 1, bg: 0 };
+        material.set(1, 2, &TileId::new(), &colors);
+        assert_eq!(dirty(&material), Some((2, 3)));
+        material.set(0, 0, &TileId::new(), &colors);
+        material.set(2, 1, &TileId::new(), &colors);
+        assert_eq!(dirty(&material), Some((0, 3)));
+
+        // Cells outside of the grid are ignored, instead of wrapping to the next row
+        material.cells.lock().unwrap().dirty = None;
+        material.set(3, 0, &TileId::new(), &colors);
+        material.set(0, 4, &TileId::new(), &colors);
+        assert_eq!(dirty(&material), None);
+        assert_eq!(cell(&material, 0, 1), [0; 4]);
+    }
+}
\ No newline at end of file
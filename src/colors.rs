@@ -1,76 +1,166 @@
-use bevy::prelude::{Color, Commands, Component};
-use crate::{App, Plugin};
+use bevy::prelude::*;
+use image::GenericImageView;
+use crate::Canvas;
 
-#[derive(Component)]
+/// Palette index of the transparent color, which shows the layers below.
+pub const TRANSPARENT: usize = usize::MAX;
+
+/// Color of palette indices past the end of the palette, which stands out instead of hiding
+/// the broken cells behind another entry.
+pub const MISSING: Color = Color::FUCHSIA;
+
+/// Indexed palette used to color the tiles.
+#[derive(Clone)]
 pub struct Colors {
-    pub black: Color,
-    pub blue_0: Color,
-    pub blue_1: Color,
-    pub blue_2: Color,
-    pub blue_3: Color,
-    pub blue_4: Color,
-    pub red_0: Color,
-    pub red_1: Color,
-    pub red_2: Color,
-    pub red_3: Color,
-    pub red_4: Color,
-    pub yellow_0: Color,
-    pub yellow_1: Color,
-    pub yellow_2: Color,
-    pub yellow_3: Color,
-    pub yellow_4: Color,
+    colors: Vec<Color>,
 }
 
 impl Colors {
-    pub(crate) fn get(&self, i: usize) -> Color {
-        match i % 15 {
-            1 => self.blue_0,
-            2 => self.blue_1,
-            3 => self.blue_2,
-            4 => self.blue_3,
-            5 => self.blue_4,
-            6 => self.red_0,
-            7 => self.red_1,
-            8 => self.red_2,
-            9 => self.red_3,
-            10 => self.red_4,
-            11 => self.yellow_0,
-            12 => self.yellow_1,
-            13 => self.yellow_2,
-            14 => self.yellow_3,
-            15 => self.yellow_4,
-            _ => self.black,
+    pub fn new(colors: Vec<Color>) -> Result<Self, String> {
+        if colors.is_empty() { return Err("The palette is empty".to_string()); }
+        Ok(Colors { colors })
+    }
+
+    /// Reads the colors of a palette image in reading order, skipping transparent pixels.
+    ///
+    /// A 1px high strip has one entry per pixel, duplicates included, so strips written by
+    /// palette editors load back with the same indices. In grids of larger swatches (like
+    /// `assets/palette.png`) already seen colors are skipped.
+    pub fn from_image(path: &str) -> Result<Self, String> {
+        let img = image::open(path).map_err(|e| format!("Couldn't open {}: {}", path, e))?;
+        let strip = img.height() == 1;
+        let mut colors: Vec<Color> = vec![];
+        for (_, _, pixel) in img.pixels() {
+            let [r, g, b, a] = pixel.0;
+            if a == 0 { continue; }
+            let color = Color::rgb_u8(r, g, b);
+            if strip || !colors.contains(&color) { colors.push(color); }
         }
+        Colors::new(colors)
     }
-}
 
-pub struct ColorPlugin;
+    pub fn get(&self, i: usize) -> Color {
+        if i == TRANSPARENT { return Color::NONE; }
+        self.colors.get(i).copied().unwrap_or(MISSING)
+    }
 
-impl Plugin for ColorPlugin {
-    fn build(&self, app: &mut App) {
-        app.add_startup_system(setup);
+    /// Whether cells can use index `i`: an entry of the palette or [`TRANSPARENT`].
+    pub fn contains(&self, i: usize) -> bool {
+        i == TRANSPARENT || i < self.colors.len()
     }
+
+    /// Color of entry `i` on a layer of the given opacity.
+    pub fn faded(&self, i: usize, opacity: f32) -> Color {
+        let mut color = self.get(i);
+        color.set_a(color.a() * opacity);
+        color
+    }
+
+    pub fn set(&mut self, i: usize, color: Color) {
+        self.colors[i] = color;
+    }
+
+    pub fn push(&mut self, color: Color) {
+        self.colors.push(color);
+    }
+
+    pub fn remove(&mut self, i: usize) -> Result<(), String> {
+        if self.colors.len() == 1 { return Err("The palette can't be empty".to_string()); }
+        self.colors.remove(i);
+        Ok(())
+    }
+
+    pub fn swap(&mut self, i: usize, j: usize) {
+        self.colors.swap(i, j);
+    }
+
+    /// Index of the color closest to `color`, ignoring the entry `except`.
+    pub fn nearest(&self, color: Color, except: usize) -> Option<usize> {
+        let distance = |c: &Color| (c.r() - color.r()).powi(2) + (c.g() - color.g()).powi(2) + (c.b() - color.b()).powi(2);
+        self.colors.iter().enumerate()
+            .filter(|(i, _)| *i != except)
+            .min_by(|(_, a), (_, b)| distance(a).partial_cmp(&distance(b)).unwrap())
+            .map(|(i, _)| i)
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Color> {
+        self.colors.iter()
+    }
+}
+
+/// `rrggbb` representation of `color`.
+pub fn to_hex(color: Color) -> String {
+    let [r, g, b, _] = color.as_rgba_f32().map(|c| (c * 255.).round() as u8);
+    format!("{:02x}{:02x}{:02x}", r, g, b)
 }
 
-fn setup(
-    mut commands: Commands
+pub(crate) fn setup(
+    mut commands: Commands,
+    canvas: Res<Canvas>,
 ) {
-    commands.insert_resource(Colors {
-        black: Color::hex("010103").unwrap(),
-        blue_0: Color::hex("161a2d").unwrap(),
-        blue_1: Color::hex("1f3846").unwrap(),
-        blue_2: Color::hex("323f74").unwrap(),
-        blue_3: Color::hex("20b7af").unwrap(),
-        blue_4: Color::hex("6dc8d7").unwrap(),
-        red_0: Color::hex("871b12").unwrap(),
-        red_1: Color::hex("e94b50").unwrap(),
-        red_2: Color::hex("f19cd1").unwrap(),
-        red_3: Color::hex("ffc9f7").unwrap(),
-        red_4: Color::hex("ffe9fc").unwrap(),
-        yellow_0: Color::hex("774b1a").unwrap(),
-        yellow_1: Color::hex("e18a1a").unwrap(),
-        yellow_2: Color::hex("f6d32d").unwrap(),
-        yellow_3: Color::hex("e5ca9f").unwrap(),
-        yellow_4: Color::hex("eee4ca").unwrap(),
-    });
-}
\ No newline at end of file
+    commands.insert_resource(Colors::from_image(&canvas.palette).expect("Couldn't load palette"));
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const CLEAR: Rgba<u8> = Rgba([255, 255, 255, 0]);
+
+    /// Loads an image with `rows` of pixels, written to the temp directory as `name`.
+    fn load(name: &str, rows: &[&[Rgba<u8>]]) -> Result<Vec<Color>, String> {
+        let mut img = RgbaImage::new(rows[0].len() as u32, rows.len() as u32);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &pixel) in row.iter().enumerate() {
+                img.put_pixel(x as u32, y as u32, pixel);
+            }
+        }
+        let path = std::env::temp_dir().join(name);
+        img.save(&path).unwrap();
+        Colors::from_image(path.to_str().unwrap()).map(|colors| colors.iter().copied().collect())
+    }
+
+    fn rgb([r, g, b, _]: [u8; 4]) -> Color {
+        Color::rgb_u8(r, g, b)
+    }
+
+    #[test]
+    fn strips_keep_every_pixel() {
+        let colors = load("bevy_textmode_strip.png", &[&[RED, GREEN, CLEAR, RED]]).unwrap();
+        assert_eq!(colors, vec![rgb(RED.0), rgb(GREEN.0), rgb(RED.0)]);
+    }
+
+    #[test]
+    fn swatches_are_read_in_reading_order() {
+        let colors = load("bevy_textmode_swatches.png", &[
+            &[RED, RED, GREEN, GREEN],
+            &[RED, RED, GREEN, GREEN],
+            &[BLUE, BLUE, CLEAR, CLEAR],
+            &[BLUE, BLUE, CLEAR, CLEAR],
+        ]).unwrap();
+        assert_eq!(colors, vec![rgb(RED.0), rgb(GREEN.0), rgb(BLUE.0)]);
+    }
+
+    #[test]
+    fn grids_skip_seen_colors() {
+        let colors = load("bevy_textmode_dedupe.png", &[
+            &[GREEN, RED, GREEN],
+            &[BLUE, GREEN, RED],
+        ]).unwrap();
+        assert_eq!(colors, vec![rgb(GREEN.0), rgb(RED.0), rgb(BLUE.0)]);
+        assert!(load("bevy_textmode_clear.png", &[&[CLEAR, CLEAR], &[CLEAR, CLEAR]]).is_err());
+        assert!(Colors::from_image("missing/bevy_textmode_palette.png").is_err());
+    }
+}
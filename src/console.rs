@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy::sprite::MaterialMesh2dBundle;
+use crate::{App, BasicMesh, Canvas, CanvasMaterial, Cell, Colors, TileColors, TileId, Tiles};
+
+/// Draws a [`Console`] the size of the [`Canvas`] of the [`TextModePlugin`](crate::TextModePlugin).
+///
+/// The console is inserted before the startup systems of the game run.
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_startup_system_to_stage(StartupStage::PreStartup, insert_console)
+            // Tiles, meshes and colors are inserted during the startup stage
+            .add_startup_system_to_stage(StartupStage::PostStartup, spawn_console)
+            .add_system_to_stage(CoreStage::PostUpdate, apply_console)
+            .add_system_to_stage(CoreStage::PostUpdate, update_console_palette);
+    }
+}
+
+/// Grid of cells drawn by the [`ConsolePlugin`], with `(0, 0)` at the top left.
+///
+/// Writes are only recorded, the changed cells are sent to the renderer once per frame.
+pub struct Console {
+    width: u32,
+    height: u32,
+    /// Indexed by `x + y * width`, top row first.
+    cells: Vec<Cell>,
+    /// Glyphs of the characters written by [`Console::print`] besides ASCII.
+    chars: HashMap<char, usize>,
+    /// Indices of the cells written since the last frame.
+    dirty: Vec<usize>,
+    /// Set when most cells changed, every cell is sent again.
+    redraw: bool,
+}
+
+impl Console {
+    pub fn new(width: u32, height: u32) -> Self {
+        Console {
+            width,
+            height,
+            cells: vec![Cell::empty(); (width * height) as usize],
+            chars: HashMap::new(),
+            dirty: vec![],
+            redraw: true,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<Cell> {
+        self.index(x, y).map(|i| self.cells[i])
+    }
+
+    /// Writes a cell, positions outside of the console are ignored.
+    pub fn set(&mut self, x: u32, y: u32, glyph: impl Into<TileId>, fg: usize, bg: usize) {
+        let cell = Cell { id: glyph.into(), fg, bg };
+        if let Some(i) = self.index(x, y) {
+            if self.cells[i] == cell { return; }
+            self.cells[i] = cell;
+            if !self.redraw { self.dirty.push(i); }
+        }
+    }
+
+    /// Writes `text` from `(x, y)` to the right, each line starting again at `x` on the next row.
+    /// Characters past the edges are clipped, control and unmapped ones are skipped.
+    pub fn print(&mut self, x: u32, y: u32, text: &str, fg: usize, bg: usize) {
+        for (row, line) in text.lines().enumerate() {
+            let y = match u32::try_from(row).ok().and_then(|row| y.checked_add(row)) {
+                Some(y) if y < self.height => y,
+                _ => break,
+            };
+            for (column, c) in line.chars().enumerate() {
+                let x = match u32::try_from(column).ok().and_then(|column| x.checked_add(column)) {
+                    Some(x) if x < self.width => x,
+                    _ => break,
+                };
+                if c.is_control() { continue; }
+                if let Some(glyph) = self.glyph(c) {
+                    self.set(x, y, glyph, fg, bg);
+                }
+            }
+        }
+    }
+
+    /// Writes every cell of the rectangle at `(x, y)`, clipped to the console.
+    #[allow(clippy::too_many_arguments)]
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, glyph: impl Into<TileId>, fg: usize, bg: usize) {
+        let id = glyph.into();
+        for y in y..y.saturating_add(height).min(self.height) {
+            for x in x..x.saturating_add(width).min(self.width) {
+                self.set(x, y, id, fg, bg);
+            }
+        }
+    }
+
+    /// Resets every cell to glyph 0 on color 0.
+    pub fn clear(&mut self) {
+        self.cells.fill(Cell::empty());
+        self.redraw_all();
+    }
+
+    /// Moves every row up by `n`, the bottom rows being cleared.
+    pub fn scroll_up(&mut self, n: u32) {
+        let n = (n.min(self.height) * self.width) as usize;
+        if n == 0 { return; }
+        self.cells.rotate_left(n);
+        let len = self.cells.len();
+        self.cells[len - n..].fill(Cell::empty());
+        self.redraw_all();
+    }
+
+    /// Glyph written by [`Console::print`] for `c`, its code for ASCII characters unless mapped.
+    pub fn glyph(&self, c: char) -> Option<usize> {
+        match self.chars.get(&c) {
+            Some(&glyph) => Some(glyph),
+            None if c.is_ascii() => Some(c as usize),
+            None => None,
+        }
+    }
+
+    /// Sets the glyph written by [`Console::print`] for `c`.
+    pub fn map_char(&mut self, c: char, glyph: usize) {
+        self.chars.insert(c, glyph);
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x >= self.width || y >= self.height { return None; }
+        Some((x + y * self.width) as usize)
+    }
+
+    fn redraw_all(&mut self) {
+        self.redraw = true;
+        self.dirty.clear();
+    }
+}
+
+/// Quad drawing the [`Console`].
+#[derive(Component)]
+pub struct ConsoleQuad {
+    pub material: Handle<CanvasMaterial>,
+}
+
+fn insert_console(mut commands: Commands, canvas: Res<Canvas>) {
+    commands.insert_resource(Console::new(canvas.width, canvas.height));
+}
+
+fn spawn_console(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<CanvasMaterial>>,
+    canvas: Res<Canvas>,
+    tiles: Res<Tiles>,
+    meshes: Res<BasicMesh>,
+    colors: Res<Colors>,
+) {
+    let palette = colors.iter().copied().collect::<Vec<Color>>();
+    let material = materials.add(CanvasMaterial::new(tiles.atlas.clone(), canvas.tile_size, canvas.width, canvas.height, &palette, 1.));
+    let tile = canvas.tile_size as f32;
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes.canvas.clone().into(),
+            material: material.clone(),
+            transform: Transform {
+                translation: (canvas.center() + canvas.offset).extend(0.),
+                scale: Vec3::new(canvas.width as f32 * tile, canvas.height as f32 * tile, 1.),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(ConsoleQuad { material });
+}
+
+/// Copies the cells written during the frame to the data texture of the console.
+fn apply_console(
+    mut console: ResMut<Console>,
+    materials: Res<Assets<CanvasMaterial>>,
+    tiles: Res<Tiles>,
+    quads: Query<&ConsoleQuad>,
+) {
+    if !console.redraw && console.dirty.is_empty() { return; }
+    let material = match quads.get_single().ok().and_then(|quad| materials.get(&quad.material)) {
+        Some(material) => material,
+        None => return,
+    };
+
+    let dirty = if console.redraw { (0..console.cells.len()).collect() } else { std::mem::take(&mut console.dirty) };
+    for i in dirty {
+        let cell = console.cells[i];
+        // The rows of the material are y up
+        let (x, y) = (i as u32 % console.width, console.height - 1 - i as u32 / console.width);
+        let id = if tiles.contains(&cell.id) { cell.id } else { TileId::new() };
+        material.set(x, y, &id, &TileColors { fg: cell.fg, bg: cell.bg });
+    }
+    console.redraw = false;
+}
+
+fn update_console_palette(
+    colors: Res<Colors>,
+    mut materials: ResMut<Assets<CanvasMaterial>>,
+    quads: Query<&ConsoleQuad>,
+) {
+    if !colors.is_changed() { return; }
+    let palette = colors.iter().copied().collect::<Vec<Color>>();
+    for quad in quads.iter() {
+        if let Some(material) = materials.get_mut(&quad.material) { material.set_palette(&palette); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyphs(console: &Console, y: u32) -> String {
+        (0..console.width()).map(|x| console.get(x, y).unwrap().id.index as u8 as char).collect()
+    }
+
+    fn blank() -> Console {
+        let mut console = Console::new(4, 3);
+        console.fill_rect(0, 0, 4, 3, '.' as usize, 1, 0);
+        console
+    }
+
+    #[test]
+    fn print_clips_at_the_edges() {
+        let mut console = blank();
+        console.print(2, 1, "abc\ndef\nghi", 2, 3);
+        assert_eq!(glyphs(&console, 0), "....");
+        assert_eq!(glyphs(&console, 1), "..ab");
+        assert_eq!(glyphs(&console, 2), "..de");
+        assert_eq!(console.get(3, 2), Some(Cell { id: TileId::from('e' as usize), fg: 2, bg: 3 }));
+
+        console.print(u32::MAX, u32::MAX, "far", 0, 0);
+        console.print(0, 5, "below", 0, 0);
+        assert_eq!(glyphs(&console, 2), "..de");
+    }
+
+    #[test]
+    fn print_skips_control_and_unmapped_chars() {
+        let mut console = blank();
+        console.map_char('é', 130);
+        console.print(0, 0, "a\té\u{7f}\r\nλx", 1, 0);
+        assert_eq!(console.get(2, 0).unwrap().id.index, 130);
+        console.set(2, 0, '.' as usize, 1, 0);
+        assert_eq!(glyphs(&console, 0), "a...");
+        assert_eq!(glyphs(&console, 1), ".x..");
+    }
+
+    #[test]
+    fn fill_rect_saturates() {
+        let mut console = blank();
+        console.fill_rect(1, 1, u32::MAX, u32::MAX, '#' as usize, 1, 0);
+        assert_eq!(glyphs(&console, 0), "....");
+        assert_eq!(glyphs(&console, 1), ".###");
+        assert_eq!(glyphs(&console, 2), ".###");
+        console.fill_rect(5, 0, 2, 2, '#' as usize, 1, 0);
+        assert_eq!(glyphs(&console, 0), "....");
+    }
+
+    #[test]
+    fn scroll_up_clears_the_bottom_rows() {
+        let mut console = blank();
+        console.print(0, 0, "ab\ncd\nef", 1, 0);
+        console.scroll_up(1);
+        assert_eq!(glyphs(&console, 0), "cd..");
+        assert_eq!(glyphs(&console, 1), "ef..");
+        assert_eq!(console.get(0, 2), Some(Cell::empty()));
+        console.scroll_up(10);
+        assert!((0..3).all(|y| console.get(0, y) == Some(Cell::empty())));
+    }
+
+    #[test]
+    fn clear_redraws_every_cell() {
+        let mut console = blank();
+        console.redraw = false;
+        console.set(1, 1, 5usize, 1, 0);
+        console.set(1, 1, 5usize, 1, 0);
+        assert_eq!(console.dirty, vec![5]);
+
+        console.clear();
+        assert!(console.redraw && console.dirty.is_empty());
+        assert_eq!(console.get(1, 1), Some(Cell::empty()));
+        assert_eq!(console.get(4, 0), None);
+        assert_eq!(console.get(0, 3), None);
+    }
+}
@@ -0,0 +1,42 @@
+//! Text mode graphics for Bevy: a grid of glyphs from a tileset, each colored with two
+//! entries of an indexed palette.
+//!
+//! [`TextModePlugin`] loads the tileset and palette of a [`Canvas`]. Cells are drawn either one
+//! entity per cell with a [`TextModeBundle`], or a whole grid at once with a [`CanvasMaterial`].
+//! Games can also add the [`ConsolePlugin`] and write to the [`Console`] resource.
+//!
+//! The editor binary is built with the `editor` feature: `cargo run --features editor`.
+
+use bevy::prelude::*;
+
+mod canvas_material;
+mod colors;
+mod console;
+mod tile_material;
+mod tiles;
+
+pub use canvas_material::CanvasMaterial;
+pub use colors::{Colors, MISSING, to_hex, TRANSPARENT};
+pub use console::{Console, ConsolePlugin, ConsoleQuad};
+pub use tile_material::TileMaterial;
+pub use tiles::{BasicMesh, Cell, init_spritesheet, TextModeBuilder, TextModeBundle, TextModePlugin, TileColors, TileId, TilePos, Tiles};
+
+/// Tileset, palette and size of the grid, inserted as a resource by [`TextModePlugin`].
+#[derive(Debug, Clone)]
+pub struct Canvas {
+    pub tileset: String,
+    pub palette: String,
+    pub tile_size: u32,
+    pub width: u32,
+    pub height: u32,
+    /// World position of the center of the bottom left cell.
+    pub offset: Vec2,
+}
+
+impl Canvas {
+    /// World position of the center of the grid.
+    pub fn center(&self) -> Vec2 {
+        let tile = self.tile_size as f32;
+        Vec2::new(self.width.saturating_sub(1) as f32 / 2. * tile, self.height.saturating_sub(1) as f32 / 2. * tile)
+    }
+}
@@ -3,17 +3,21 @@ use bevy::ecs::system::SystemParamItem;
 use bevy::prelude::*;
 use bevy::reflect::TypeUuid;
 use bevy::render::render_asset::{PrepareAssetError, RenderAsset, RenderAssets};
-use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, BufferInitDescriptor, BufferSize, BufferUsages, SamplerBindingType, ShaderStages, TextureSampleType, TextureViewDimension};
+use bevy::render::render_resource::{BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType, BufferInitDescriptor, BufferSize, BufferUsages, ShaderStages, TextureSampleType, TextureViewDimension};
 use bevy::render::render_resource::std140::{AsStd140, Std140};
 use bevy::render::renderer::RenderDevice;
 use bevy::sprite::{Material2d, Material2dPipeline};
+use crate::TileId;
 
 #[derive(Debug, Clone, Component, TypeUuid)]
 #[uuid = "eb3bfce5-5e0d-4a0e-bf7c-dec3e8a6d330"]
 pub struct TileMaterial {
-    pub(crate) texture: Handle<Image>,
-    pub(crate) bg: Color,
-    pub(crate) fg: Color,
+    pub atlas: Handle<Image>,
+    /// Glyph of the atlas, flipped and rotated by the shader.
+    pub id: TileId,
+    pub tile_size: u32,
+    pub bg: Color,
+    pub fg: Color,
 }
 
 #[derive(Clone)]
@@ -37,12 +41,14 @@ impl RenderAsset for TileMaterial {
         extracted_asset: Self::ExtractedAsset,
         (render_device, gpu_images, material_pipeline): &mut SystemParamItem<Self::Param>,
     ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
-        let gpu_image = match gpu_images.get(&extracted_asset.texture) {
+        let gpu_image = match gpu_images.get(&extracted_asset.atlas) {
             Some(gpu_image) => gpu_image,
             // if the image isn't loaded yet, try next frame
             None => return Err(PrepareAssetError::RetryNextUpdate(extracted_asset)),
         };
 
+        let id = extracted_asset.id;
+        let glyph = Vec4::new(id.index as f32, id.flip as u8 as f32, (id.rotation % 4) as f32, extracted_asset.tile_size as f32);
         let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
             entries: &[
                 BindGroupEntry {
@@ -51,7 +57,11 @@ impl RenderAsset for TileMaterial {
                 },
                 BindGroupEntry {
                     binding: 1,
-                    resource: BindingResource::Sampler(&gpu_image.sampler),
+                    resource: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                        contents: glyph.as_std140().as_bytes(),
+                        label: None,
+                        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                    }).as_entire_binding()
                 },
                 BindGroupEntry {
                     binding: 2,
@@ -99,7 +109,11 @@ impl Material2d for TileMaterial {
                 BindGroupLayoutEntry {
                     binding: 1,
                     visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: BufferSize::new(Vec4::std140_size_static() as u64),
+                    },
                     count: None,
                 },
                 BindGroupLayoutEntry {
@@ -1,30 +1,105 @@
-use std::collections::HashMap;
+// The Bundle derive of Bevy 0.7 forgets each field once it's moved into the world
+#![allow(clippy::forget_non_drop)]
+
 use bevy::app::Plugin;
 use bevy::prelude::*;
+use bevy::render::render_asset::PrepareAssetLabel;
 use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
-use bevy::sprite::Mesh2dHandle;
+use bevy::render::{RenderApp, RenderStage};
+use bevy::sprite::{Material2dPlugin, Mesh2dHandle};
 use image::GenericImageView;
-use crate::{App, Canvas};
+use serde::{Deserialize, Serialize};
+use crate::{App, Canvas, Colors, colors, TRANSPARENT};
+use crate::canvas_material::{CanvasMaterial, write_canvas_cells};
 use crate::tile_material::TileMaterial;
 
-pub struct TextModePlugin;
+/// Registers the tile materials, then loads the [`Tiles`], [`BasicMesh`] and
+/// [`Colors`] of the [`Canvas`] at startup.
+pub struct TextModePlugin {
+    canvas: Canvas,
+    colors: Option<Colors>,
+}
+
+impl TextModePlugin {
+    /// Settings for the tileset image, with its glyphs in a grid and black pixels being the
+    /// background, and the palette image, see [`Colors::from_image`].
+    pub fn builder(tileset: &str, tile_size: u32, palette: &str) -> TextModeBuilder {
+        assert!(tile_size > 0, "The tile size must be at least 1px");
+        TextModeBuilder {
+            canvas: Canvas {
+                tileset: tileset.to_string(),
+                palette: palette.to_string(),
+                tile_size,
+                width: 32,
+                height: 18,
+                offset: Vec2::ZERO,
+            },
+            colors: None,
+        }
+    }
+}
 
 impl Plugin for TextModePlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(setup);
+        app
+            .add_plugin(Material2dPlugin::<TileMaterial>::default())
+            .add_plugin(Material2dPlugin::<CanvasMaterial>::default())
+            .insert_resource(self.canvas.clone())
+            .add_startup_system(setup);
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.add_system_to_stage(RenderStage::Prepare, write_canvas_cells.after(PrepareAssetLabel::AssetPrepare));
+        }
+        match &self.colors {
+            Some(colors) => { app.insert_resource(colors.clone()); }
+            None => { app.add_startup_system(colors::setup); }
+        }
+    }
+}
+
+/// Settings of a [`TextModePlugin`], a 32×18 canvas by default.
+pub struct TextModeBuilder {
+    canvas: Canvas,
+    colors: Option<Colors>,
+}
+
+impl TextModeBuilder {
+    /// Palette used instead of reading the palette image, for palettes loaded from other formats.
+    pub fn colors(mut self, colors: Colors) -> Self {
+        self.colors = Some(colors);
+        self
+    }
+
+    /// Size of the canvas in cells.
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        assert!(width > 0 && height > 0, "The canvas must be at least 1×1");
+        self.canvas.width = width;
+        self.canvas.height = height;
+        self
+    }
+
+    /// World position of the center of the bottom left cell.
+    pub fn offset(mut self, offset: Vec2) -> Self {
+        self.canvas.offset = offset;
+        self
+    }
+
+    pub fn build(self) -> TextModePlugin {
+        TextModePlugin { canvas: self.canvas, colors: self.colors }
     }
 }
 
 #[derive(Component)]
 pub struct BasicMesh {
-    pub(crate) tile: Handle<Mesh>
+    pub tile: Handle<Mesh>,
+    /// 1×1 quad scaled to the size of the canvas.
+    pub canvas: Handle<Mesh>,
 }
 
-#[derive(Component, Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Component, Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct TileId {
-    pub(crate) index: usize,
-    pub(crate) flip: bool,
-    pub(crate) rotation: u8,
+    pub index: usize,
+    pub flip: bool,
+    pub rotation: u8,
 }
 
 impl TileId {
@@ -43,11 +118,99 @@ impl TileId {
     pub fn flip(&mut self) {
         self.flip = !self.flip;
     }
+
+    /// Pixel of the glyph drawn at `(x, y)` of the tile, y down: the glyph is mirrored
+    /// horizontally when flipped, then turned a quarter counterclockwise per rotation.
+    /// The shaders apply the same transform to UVs.
+    pub fn source(&self, x: u32, y: u32, size: u32) -> (u32, u32) {
+        let (mut x, mut y) = (x, y);
+        for _ in 0..self.rotation % 4 {
+            (x, y) = (size - 1 - y, x);
+        }
+        if self.flip { x = size - 1 - x; }
+        (x, y)
+    }
 }
 
-#[derive(Component)]
+impl Default for TileId {
+    fn default() -> Self {
+        TileId::new()
+    }
+}
+
+/// Glyph `index` of the tileset, neither flipped nor rotated.
+impl From<usize> for TileId {
+    fn from(index: usize) -> Self {
+        TileId { index, ..TileId::new() }
+    }
+}
+
+/// Palette indices of a grid cell.
+#[derive(Component, Copy, Clone, Eq, PartialEq)]
+pub struct TileColors {
+    pub fg: usize,
+    pub bg: usize,
+}
+
+/// Content of a grid cell: a tile and its palette colors.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Cell {
+    pub id: TileId,
+    pub fg: usize,
+    pub bg: usize,
+}
+
+impl Cell {
+    /// Glyph 0 on color 0, the cell of empty canvases and consoles.
+    pub fn empty() -> Self {
+        Cell { id: TileId::new(), fg: 0, bg: 0 }
+    }
+
+    /// A cell showing the layers below.
+    pub fn transparent() -> Self {
+        Cell { id: TileId::new(), fg: TRANSPARENT, bg: TRANSPARENT }
+    }
+
+    /// Changes the palette indices of the cell after palette entries were moved or removed,
+    /// [`TRANSPARENT`] isn't a palette entry and stays the same.
+    pub fn remap(&mut self, f: &dyn Fn(usize) -> usize) {
+        let remap = |index| if index == TRANSPARENT { index } else { f(index) };
+        self.fg = remap(self.fg);
+        self.bg = remap(self.bg);
+    }
+}
+
+#[derive(Component, Clone)]
 pub struct Tiles {
-    pub(crate) tiles: HashMap<TileId, Handle<Image>>
+    /// Every glyph in a single image, in the tileset layout. Flips and rotations are applied by
+    /// the shaders.
+    pub atlas: Handle<Image>,
+    /// Foreground pixels of the atlas, row by row.
+    pixels: Vec<bool>,
+    /// Tile size in pixels.
+    size: u32,
+    /// Number of tiles per row in the tileset image.
+    pub columns: usize,
+    pub rows: usize,
+}
+
+impl Tiles {
+    pub fn count(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// Whether `id` is a glyph of the tileset.
+    pub fn contains(&self, id: &TileId) -> bool {
+        id.index < self.count() && id.rotation < 4
+    }
+
+    /// Whether the pixel `(x, y)` of the tile `id`, y down, is drawn with the foreground color.
+    pub fn lit(&self, id: &TileId, x: u32, y: u32) -> bool {
+        let (x, y) = id.source(x, y, self.size);
+        let (x0, y0) = ((id.index % self.columns) as u32 * self.size, (id.index / self.columns) as u32 * self.size);
+        let width = self.columns as u32 * self.size;
+        self.pixels.get(((x0 + x) + (y0 + y) * width) as usize).copied().unwrap_or(false)
+    }
 }
 
 #[derive(Component, Copy, Clone, Eq, PartialEq)]
@@ -69,9 +232,10 @@ pub struct TextModeBundle {
 }
 
 impl TextModeBundle {
-    pub(crate) fn new(
-        tiles: &Res<Tiles>,
-        materials: &mut ResMut<Assets<TileMaterial>>,
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        tiles: &Tiles,
+        materials: &mut Assets<TileMaterial>,
         id: &TileId,
         x: u32,
         y: u32,
@@ -80,13 +244,12 @@ impl TextModeBundle {
         mesh: Handle<Mesh>,
         canvas: &Canvas,
     ) -> Self {
-        let texture = tiles.tiles.get(id).expect("Couldn't find tile.");
         let tile = canvas.tile_size as f32;
         TextModeBundle {
             pos: TilePos { x, y },
-            id: id.clone(),
+            id: *id,
             mesh: mesh.into(),
-            material: materials.add(TileMaterial { texture: texture.clone(), bg, fg }),
+            material: materials.add(TileMaterial { atlas: tiles.atlas.clone(), id: *id, tile_size: canvas.tile_size, bg, fg }),
             transform: Transform {
                 translation: Vec3::new(x as f32 * tile + canvas.offset.x, y as f32 * tile + canvas.offset.y, 0.0),
                 ..Default::default()
@@ -100,103 +263,107 @@ impl TextModeBundle {
 
 fn setup(
     mut commands: Commands,
-    images: ResMut<Assets<Image>>,
+    mut images: ResMut<Assets<Image>>,
     canvas: Res<Canvas>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    init_spritesheet("assets/MRMOTEXT.png", canvas.tile_size, images, &mut commands);
-    let tile = canvas.tile_size as f32;
+    let tiles = init_spritesheet(&canvas.tileset, canvas.tile_size, &mut images).expect("Couldn't load tileset");
+    commands.insert_resource(tiles);
+    commands.insert_resource(BasicMesh::new(canvas.tile_size, &mut meshes));
+}
 
-    commands.insert_resource(BasicMesh {
-        tile: meshes.add(Mesh::from(shape::Quad::new(Vec2::new(tile, tile))))
-    });
+impl BasicMesh {
+    pub fn new(tile_size: u32, meshes: &mut Assets<Mesh>) -> Self {
+        let tile = tile_size as f32;
+        BasicMesh {
+            tile: meshes.add(Mesh::from(shape::Quad::new(Vec2::new(tile, tile)))),
+            canvas: meshes.add(Mesh::from(shape::Quad::new(Vec2::ONE))),
+        }
+    }
 }
 
-fn init_spritesheet(
+pub fn init_spritesheet(
     path: &str,
     size: u32,
-    mut images: ResMut<Assets<Image>>,
-    commands: &mut Commands,
-) {
-    let img = image::open(path).expect("File not found");
+    images: &mut Assets<Image>,
+) -> Result<Tiles, String> {
+    if size == 0 { return Err("Tile size must be positive".to_string()); }
+    let img = image::open(path).map_err(|e| format!("Couldn't open {}: {}", path, e))?;
     let tile_width = img.width() / size;
     let tile_height = img.height() / size;
-    let mut tiles_vec: Vec<Vec<(u8, u8, u8, u8)>> = vec![];
-    for _i in 0..(tile_width * tile_height) {
-        tiles_vec.push(vec![]);
-    }
-    for pixel in img.pixels() {
-        let x = pixel.0;
-        let y = pixel.1;
-        let n = x / size + y / size * tile_width;
-        if let Some(tile) = tiles_vec.get_mut(n as usize) {
-            match pixel.2.0 {
-                [0, 0, 0, _] => {
-                    tile.push((0, 0, 0, 255));
-                }
-                _ => {
-                    tile.push((255, 255, 255, 255));
-                }
-            }
-        }
+    if tile_width == 0 || tile_height == 0 {
+        return Err(format!("{} is smaller than a {}px tile", path, size));
     }
 
-    let mut tiles_hm = HashMap::new();
-    let extent3d = Extent3d {
-        width: size,
-        height: size,
-        depth_or_array_layers: 1
-    };
-
-    let get_handle = |id: &TileId, input: &Vec<(u8, u8, u8, u8)>| Image::new(
-        extent3d.clone(),
-        TextureDimension::D2,
-        flip(id, size, input.clone()),
-        TextureFormat::Rgba8UnormSrgb
-    );
-
-    for index in 0..tiles_vec.len() {
-        for flip in 0..2 {
-            for rotation in 0..4 {
-                let id = TileId { index, flip: flip == 1, rotation };
-                let handle = images.add(get_handle(&id, tiles_vec.get(index).unwrap()));
-                tiles_hm.insert(id, handle);
-            }
+    // Pixels after the last full tile are cropped, black pixels are the background
+    let (width, height) = (tile_width * size, tile_height * size);
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            pixels.push(!matches!(img.get_pixel(x, y).0, [0, 0, 0, _]));
         }
     }
+    let data = pixels.iter()
+        .flat_map(|&lit| if lit { [255, 255, 255, 255] } else { [0, 0, 0, 255] })
+        .collect();
+    let atlas = images.add(Image::new(
+        Extent3d { width, height, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb
+    ));
 
-    commands.insert_resource(Tiles { tiles: tiles_hm });
+    Ok(Tiles { atlas, pixels, size, columns: tile_width as usize, rows: tile_height as usize })
 }
 
-fn flip(id: &TileId, size: u32, input: Vec<(u8, u8, u8, u8)>) -> Vec<u8> {
-    let mut result = vec![];
-    let mut step = vec![];
-    let mut input = input.clone();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    while input.len() > 0 {
-        let x = input.remove(0);
-        step.push(x);
-        if step.len() == size as usize {
-            result.push(step.clone());
-            step.clear();
+    /// Rows of a `size`×`size` glyph flipped and rotated the way tilesets were baked before the
+    /// shaders did it, each pixel being its index in the source glyph.
+    fn baked(id: &TileId, size: u32) -> Vec<Vec<u32>> {
+        let mut rows = (0..size).map(|y| (0..size).map(|x| x + y * size).collect()).collect::<Vec<Vec<u32>>>();
+        if id.flip { rows.iter_mut().for_each(|row| row.reverse()); }
+        for _ in 0..id.rotation % 4 {
+            let before = rows.clone();
+            rows.iter_mut().for_each(|row| row.clear());
+            for row in &before {
+                for j in 0..row.len() {
+                    rows[j].push(row[size as usize - j - 1]);
+                }
+            }
         }
+        rows
     }
 
-    if id.flip { result.iter_mut().for_each(|v| v.reverse()) }
+    #[test]
+    fn source_matches_baked_tiles() {
+        let size = 5;
+        for variant in 0..8 {
+            let id = TileId { index: 0, flip: variant >= 4, rotation: variant % 4 };
+            let rows = baked(&id, size);
+            for y in 0..size {
+                for x in 0..size {
+                    let (sx, sy) = id.source(x, y, size);
+                    assert_eq!(rows[y as usize][x as usize], sx + sy * size, "{:?} at ({}, {})", id, x, y);
+                }
+            }
+        }
+    }
 
-    for _i in 0..(id.rotation % 4) {
-        let before = result.clone();
-        result.iter_mut().for_each(|v| v.clear());
-        before.iter().for_each(|v| for j in 0..v.len() {
-            result.get_mut(j).unwrap().push(v.get(size as usize - j - 1).unwrap().clone());
-        })
+    #[test]
+    fn four_rotations_are_identity() {
+        let mut id = TileId::new();
+        id.flip();
+        for _ in 0..4 { id.rotate(); }
+        assert_eq!((id.source(1, 3, 8), id.rotation), ((6, 3), 0));
     }
 
-    return result.iter()
-        .flat_map(|x: &Vec<(u8, u8, u8, u8)>|
-            x.iter()
-                .flat_map(|tup: &(u8, u8, u8, u8)|
-                    [tup.0, tup.1, tup.2, tup.3].into_iter().clone()
-                )
-        ).collect::<Vec<u8>>();
+    #[test]
+    fn remap_keeps_transparent() {
+        let mut cell = Cell { id: TileId::new(), fg: 2, bg: TRANSPARENT };
+        cell.remap(&|i| i + 1);
+        assert_eq!((cell.fg, cell.bg), (3, TRANSPARENT));
+    }
 }
\ No newline at end of file